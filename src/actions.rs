@@ -1,92 +1,495 @@
 use duct::cmd;
-use std::path::Path;
-use crate::config::GiteaConfig;
-use crate::gitea;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::catalog::{self, Catalog};
+use crate::config::{ForksConfig, SourceConfig, SubmodulesConfig};
+use crate::dependency;
+use crate::destination::{PushRequest, Target};
+use crate::metadata::{MetadataArchive, ProfileArchive};
+use crate::lfs;
+use crate::pool;
+use crate::submodule;
+use crate::source::{self, with_credentials, GitCredential, RemoteRepo, Source};
 
-pub fn process_repositories(repos: &[String], output_dir: &str, user_or_org: &str, gitea_config: Option<&GiteaConfig>) -> Result<(), Box<dyn std::error::Error>> {
+/// State shared by every repository processed during a run.
+pub struct Context {
+    pub output_dir: PathBuf,
+    pub destinations: Vec<Target>,
+    pub catalog: Catalog,
+    /// Options for the git dependencies found in manifests, when they are
+    /// mirrored.
+    pub dependencies: Option<SourceConfig>,
+    /// Dependencies already mirrored during this run, by catalog key.
+    fetched_dependencies: HashSet<String>,
+    /// Credentials of the forges repositories were listed from, which git
+    /// also needs for their forks, wikis and LFS objects.
+    credentials: Vec<GitCredential>,
+    summary: BTreeMap<String, PushSummary>,
+}
+
+#[derive(Default)]
+struct PushSummary {
+    pushed: usize,
+    failed: Vec<String>,
+}
+
+impl Context {
+    pub fn new(output_dir: &Path, destinations: Vec<Target>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Context {
+            output_dir: output_dir.to_path_buf(),
+            catalog: Catalog::load(output_dir)?,
+            destinations,
+            dependencies: None,
+            fetched_dependencies: HashSet::new(),
+            credentials: Vec::new(),
+            summary: BTreeMap::new(),
+        })
+    }
+
+    /// Prints how many repositories were pushed to each destination and
+    /// which ones failed.
+    pub fn print_summary(&self) {
+        for target in &self.destinations {
+            let Some(summary) = self.summary.get(&target.name) else {
+                continue;
+            };
+            println!(
+                "Destination {}: {} pushed, {} failed",
+                target.name,
+                summary.pushed,
+                summary.failed.len()
+            );
+            for full_name in &summary.failed {
+                println!("  failed: {}", full_name);
+            }
+        }
+    }
+}
+
+/// Fetches `repos` and pushes them to the destinations. `forge` is the
+/// source they were listed from, if any, used to archive their issues.
+pub fn process_repositories(
+    ctx: &mut Context,
+    repos: &[RemoteRepo],
+    source: &SourceConfig,
+    forge: Option<&dyn Source>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(credential) = forge.and_then(|forge| forge.git_credential()) {
+        if !ctx.credentials.contains(&credential) {
+            ctx.credentials.push(credential);
+        }
+    }
     for repo in repos {
-        println!("Processing repository: {}/{}", user_or_org, repo);
-        process_repository(repo, output_dir, user_or_org, gitea_config)?;
+        println!("Processing repository: {}", repo.full_name());
+        process_repository(ctx, repo, source, forge, None)?;
     }
     Ok(())
 }
 
-fn process_repository(repo: &str, output_dir: &str, user_or_org: &str, gitea_config: Option<&GiteaConfig>) -> Result<(), Box<dyn std::error::Error>> {
-    let repo_path = format!("{}/{}.git", output_dir, repo);
-    let repo_dir = Path::new(&repo_path);
+fn process_repository(
+    ctx: &mut Context,
+    repo: &RemoteRepo,
+    source: &SourceConfig,
+    forge: Option<&dyn Source>,
+    reference: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo_path = ctx.output_dir.join(format!("{}.git", repo.path));
+
+    if let (Some(schedule), Some(last_fetched)) = (source.schedule()?, ctx.catalog.last_fetched(repo)) {
+        let age = catalog::now().saturating_sub(last_fetched);
+        if repo_path.exists() && age < schedule.as_secs() {
+            println!("Skipping {}: fetched {}s ago, schedule is {:?}", repo.full_name(), age, schedule);
+            return Ok(());
+        }
+    }
 
-    if repo_dir.exists() {
-        update_repository(&repo_path, repo)?;
+    if repo_path.exists() {
+        update_repository(&repo_path, source, &ctx.credentials)?;
     } else {
-        clone_from_github(user_or_org, repo, &repo_path)?;
+        clone_repository(&repo.clone_url, &repo_path, source, reference, &ctx.credentials)?;
+    }
+    ctx.catalog.record_fetch(repo);
+
+    if source.lfs {
+        if let Err(e) = mirror_lfs(&repo_path, source, &ctx.credentials) {
+            eprintln!("Could not fetch the LFS objects of {}: {}", repo.full_name(), e);
+        }
+    }
+
+    if source.wiki {
+        if let Some(wiki_url) = repo.wiki_url() {
+            // GitHub reports has_wiki for repositories whose wiki has no pages,
+            // which cannot be cloned
+            let wiki_path = wiki_path(&repo_path);
+            if let Err(e) = mirror_wiki(&wiki_url, &wiki_path, &ctx.credentials) {
+                eprintln!("Could not mirror the wiki of {}: {}", repo.full_name(), e);
+            }
+        }
+    }
+
+    if let Some(forge) = forge {
+        let metadata = MetadataArchive::for_mirror(&repo_path);
+        if source.issues {
+            if let Err(e) = forge.archive_issues(repo, &metadata) {
+                eprintln!("Could not archive the issues of {}: {}", repo.full_name(), e);
+            }
+        }
+        if source.releases {
+            if let Err(e) = forge.archive_releases(repo, &metadata) {
+                eprintln!("Could not archive the releases of {}: {}", repo.full_name(), e);
+            }
+        }
+    }
+
+    push_to_destinations(ctx, &repo_path, source, repo)?;
+
+    // Mirrors without a cadence are only maintained by the maintain command
+    if source.maintenance()?.is_some_and(|interval| ctx.catalog.maintenance_due(repo, Some(interval))) {
+        let borrowed = pool::borrowed(&ctx.catalog, &ctx.output_dir);
+        match maintain_repository(&repo_path, &borrowed) {
+            Ok(reclaimed) => {
+                println!("Maintained {}, reclaimed {}", repo.full_name(), pool::format_size(reclaimed));
+                ctx.catalog.record_maintenance(repo);
+                ctx.catalog.save()?;
+            }
+            Err(e) => eprintln!("Failed to maintain {}: {}", repo.full_name(), e),
+        }
+    }
+
+    if let (Some(forge), Some(forks)) = (forge, source.include_forks) {
+        process_forks(ctx, repo, &repo_path, source, forge, forks)?;
     }
 
-    if let Some(config) = gitea_config {
-        ensure_gitea_repo_exists(config, user_or_org, repo)?;
-        push_to_gitea(config, &repo_path, user_or_org, repo)?;
+    if let Some(submodules) = source.submodules {
+        process_submodules(ctx, repo, &repo_path, source, submodules)?;
+    }
+
+    process_dependencies(ctx, repo, &repo_path);
+    Ok(())
+}
+
+/// Mirrors the forks network of `root` breadth first, up to `forks.depth`
+/// levels and `forks.max` forks. Each fork shares the objects of its parent
+/// through git alternates, and its parent is recorded in the catalog.
+fn process_forks(
+    ctx: &mut Context,
+    root: &RemoteRepo,
+    root_path: &Path,
+    source: &SourceConfig,
+    forge: &dyn Source,
+    forks: ForksConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fork_source = source.for_forks();
+    let mut remaining = forks.max;
+    let mut queue = VecDeque::from([(root.clone(), root_path.to_path_buf(), 1)]);
+
+    while let Some((parent, parent_path, depth)) = queue.pop_front() {
+        if depth > forks.depth || remaining == 0 {
+            break;
+        }
+        let children = match forge.list_forks(&parent.owner, &parent.name, remaining) {
+            Ok(children) => children,
+            Err(e) => {
+                eprintln!("Could not list the forks of {}: {}", parent.full_name(), e);
+                continue;
+            }
+        };
+
+        for fork in children.into_iter().take(remaining) {
+            remaining -= 1;
+            println!("Processing fork of {}: {}", parent.full_name(), fork.full_name());
+            ctx.catalog.entry(&fork).parent = Some(parent.path.clone());
+            if let Err(e) = process_repository(ctx, &fork, &fork_source, None, Some(&parent_path)) {
+                eprintln!("Failed to mirror fork {}: {}", fork.full_name(), e);
+                continue;
+            }
+            let fork_path = ctx.output_dir.join(format!("{}.git", fork.path));
+            queue.push_back((fork, fork_path, depth + 1));
+        }
     }
+    Ok(())
+}
+
+/// Mirrors the repositories referenced as submodules on any branch or tag of
+/// `root`, breadth first up to `submodules.depth` levels. Repositories already
+/// archived from another source, such as GitHub repositories listed in the
+/// configuration, are not mirrored a second time.
+fn process_submodules(
+    ctx: &mut Context,
+    root: &RemoteRepo,
+    root_path: &Path,
+    source: &SourceConfig,
+    submodules: SubmodulesConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let submodule_source = source.for_submodules();
+    let mut seen = HashSet::from([root.path.clone()]);
+    let mut queue = VecDeque::from([(root.clone(), root_path.to_path_buf(), 1)]);
+
+    while let Some((parent, parent_path, depth)) = queue.pop_front() {
+        if depth > submodules.depth {
+            break;
+        }
+        let urls = match submodule::urls(&parent_path) {
+            Ok(urls) => urls,
+            Err(e) => {
+                eprintln!("Could not read the submodules of {}: {}", parent.full_name(), e);
+                continue;
+            }
+        };
+
+        for url in urls {
+            let child = match submodule::resolve_url(&parent.clone_url, &url).and_then(|url| source::from_network_url(&url)) {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Skipping submodule {} of {}: {}", url, parent.full_name(), e);
+                    continue;
+                }
+            };
+            if !seen.insert(child.path.clone()) {
+                continue;
+            }
+            if let Some(key) = ctx.catalog.duplicate_of(&child) {
+                println!("Skipping submodule {} of {}: archived as {}", url, parent.full_name(), key);
+                continue;
+            }
 
+            println!("Processing submodule of {}: {}", parent.full_name(), child.full_name());
+            if let Err(e) = process_repository(ctx, &child, &submodule_source, None, None) {
+                eprintln!("Failed to mirror submodule {}: {}", child.full_name(), e);
+                continue;
+            }
+            let child_path = ctx.output_dir.join(format!("{}.git", child.path));
+            queue.push_back((child, child_path, depth + 1));
+        }
+    }
     Ok(())
 }
 
-fn ensure_gitea_repo_exists(config: &GiteaConfig, user_or_org: &str, repo: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // First, ensure the organization exists
-    match gitea::create_org_if_no_conflict(&config.url, &config.token, user_or_org) {
-        Ok(created) => {
-            if created {
-                println!("Created new organization in Gitea: {}", user_or_org);
+/// Mirrors the git repositories the manifests on the default branch of `repo`
+/// depend on, with the options in `ctx.dependencies`. Dependencies of
+/// dependencies are not followed.
+fn process_dependencies(ctx: &mut Context, repo: &RemoteRepo, repo_path: &Path) {
+    // Taken for the duration of the pass, so the dependencies processed below
+    // do not look for dependencies of their own
+    let Some(dependency_source) = ctx.dependencies.take() else {
+        return;
+    };
+
+    match dependency::urls(repo_path) {
+        Ok(urls) => {
+            for url in urls {
+                // Manifests are controlled by the upstream repository, which
+                // must not point at repositories on the local disk
+                let child = match source::from_network_url(&url) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        eprintln!("Skipping dependency {} of {}: {}", url, repo.full_name(), e);
+                        continue;
+                    }
+                };
+                if child.path == repo.path
+                    || !dependency_source.accepts(&child.path)
+                    || !ctx.fetched_dependencies.insert(child.path.clone())
+                {
+                    continue;
+                }
+                if let Some(key) = ctx.catalog.duplicate_of(&child) {
+                    println!("Skipping dependency {} of {}: archived as {}", url, repo.full_name(), key);
+                    continue;
+                }
+
+                println!("Processing dependency of {}: {}", repo.full_name(), child.full_name());
+                if let Err(e) = process_repository(ctx, &child, &dependency_source, None, None) {
+                    eprintln!("Failed to mirror dependency {}: {}", child.full_name(), e);
+                }
+            }
+        }
+        Err(e) => eprintln!("Could not read the manifests of {}: {}", repo.full_name(), e),
+    }
+    ctx.dependencies = Some(dependency_source);
+}
+
+/// Pushes the mirror at `repo_path` to every destination accepting it.
+/// A failing destination is recorded in the catalog and does not prevent
+/// pushes to the others.
+pub fn push_to_destinations(ctx: &mut Context, repo_path: &Path, source: &SourceConfig, repo: &RemoteRepo) -> Result<(), Box<dyn std::error::Error>> {
+    let full_name = repo.full_name();
+    let (owner, name) = match (repo.gist, &source.gists_owner) {
+        (false, _) => (source.destination_owner(&repo.owner), repo.name.clone()),
+        (true, Some(gists_owner)) => (gists_owner.clone(), format!("{}-{}", repo.owner, repo.name)),
+        // Gists are only kept locally unless they have an owner of their own
+        (true, None) => return ctx.catalog.save(),
+    };
+    // The owner's profile is archived in its directory, next to the mirror
+    let profiles = ProfileArchive::new(repo_path.with_file_name(".profile"));
+    let profile = if repo.gist { None } else { profiles.latest()? };
+    let avatar = profile.as_ref().and_then(|profile| profiles.avatar_path(profile));
+    let request = PushRequest {
+        owner: &owner,
+        repo: &name,
+        description: repo.description.as_deref(),
+        visibility: source.visibility,
+        profile: profile.as_ref(),
+        avatar: avatar.as_deref(),
+        lfs: source.lfs && lfs::has_objects(repo_path),
+        archived: repo.archived,
+        topics: &repo.topics,
+        mirror_of: Some(repo.clone_url.as_str())
+            .filter(|url| !repo.private && (url.starts_with("https://") || url.starts_with("http://"))),
+    };
+
+    let wiki = wiki_path(repo_path);
+    let metadata = MetadataArchive::for_mirror(repo_path);
+    let state = match ctx.destinations.iter().any(Target::incremental) {
+        true => Some(fingerprint(repo_path)?),
+        false => None,
+    };
+
+    for target in &ctx.destinations {
+        if !target.accepts(&repo.owner, &repo.name) {
+            continue;
+        }
+        let state = state.clone().filter(|_| target.incremental());
+        // Metadata is still pushed, as incremental destinations skip the
+        // files that have not changed themselves
+        let unchanged = state.is_some() && ctx.catalog.pushed_state(repo, &target.name) == state.as_deref();
+        if unchanged {
+            println!("Skipping the mirror of {} for {}: unchanged since the last push", full_name, target.name);
+        }
+
+        let result = (if unchanged { Ok(()) } else { target.push(repo_path, &request) })
+            .and_then(|()| if wiki.exists() && !unchanged { target.push_wiki(&wiki, &request) } else { Ok(()) })
+            .and_then(|()| if metadata.has_issues() { target.push_issues(&metadata, &request) } else { Ok(()) })
+            .and_then(|()| if metadata.has_releases() { target.push_releases(&metadata, &request) } else { Ok(()) })
+            .and_then(|()| target.finish(&request))
+            .map_err(|e| e.to_string());
+        let summary = ctx.summary.entry(target.name.clone()).or_default();
+        match &result {
+            Ok(()) => {
+                println!("Pushed {} to {}", full_name, target.name);
+                summary.pushed += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to push {} to {}: {}", full_name, target.name, e);
+                summary.failed.push(full_name.clone());
             }
-        },
-        Err(e) => {
-            return Err(format!("Failed to create organization in Gitea: {}: {}", user_or_org, e).into());
         }
+        ctx.catalog.record_push(repo, &target.name, result, state);
+    }
+
+    ctx.catalog.save()
+}
+
+/// Fetches the LFS objects of the mirror at `repo_path` if it tracks files
+/// with LFS. Only repositories that do need git-lfs installed.
+fn mirror_lfs(repo_path: &Path, source: &SourceConfig, credentials: &[GitCredential]) -> Result<(), Box<dyn std::error::Error>> {
+    if !lfs::uses_lfs(repo_path)? {
+        return Ok(());
+    }
+    if !lfs::available() {
+        return Err("the repository uses Git LFS but git-lfs is not installed".into());
     }
+    lfs::fetch(repo_path, source.lfs_max_size()?, credentials)
+}
 
-    // Then, check if the repository exists and create it if it doesn't
-    if !gitea::check_repo_exists(&config.url, &config.token, user_or_org, repo) {
-        if gitea::create_repo(&config.url, &config.token, user_or_org, repo) {
-            println!("Created new repository in Gitea: {}/{}", user_or_org, repo);
-        } else {
-            return Err(format!("Failed to create repository in Gitea: {}/{}", user_or_org, repo).into());
+/// A digest of the refs of the mirror at `repo_path` and its wiki, which
+/// changes whenever the repositories pushed to destinations do.
+fn fingerprint(repo_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    for path in [repo_path.to_path_buf(), wiki_path(repo_path)] {
+        if path.exists() {
+            hasher.update(cmd!("git", "--git-dir", &path, "for-each-ref", "--format=%(objectname) %(refname)").read()?);
         }
     }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Location of the wiki mirror of the repository mirrored at `repo_path`.
+fn wiki_path(repo_path: &Path) -> PathBuf {
+    repo_path.with_extension("wiki.git")
+}
+
+fn mirror_wiki(url: &str, wiki_path: &Path, credentials: &[GitCredential]) -> Result<(), Box<dyn std::error::Error>> {
+    let git = match wiki_path.exists() {
+        true => cmd!("git", "--git-dir", wiki_path, "fetch", "--prune", "origin"),
+        false => cmd!("git", "clone", "--mirror", url, wiki_path),
+    };
+    with_credentials(git, credentials).run()?;
     Ok(())
 }
 
-fn clone_from_github(user_or_org: &str, repo: &str, repo_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    cmd!(
-        "git",
-        "clone",
-        "--mirror",
-        &format!("https://github.com/{}/{}.git", user_or_org, repo),
-        repo_path
-    )
-    .run()?;
+/// Clones `url` into `repo_path`. With a `reference` mirror, objects it
+/// already has are borrowed through git alternates instead of copied.
+fn clone_repository(
+    url: &str,
+    repo_path: &Path,
+    source: &SourceConfig,
+    reference: Option<&Path>,
+    credentials: &[GitCredential],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Alternates are resolved relative to the objects directory, so the
+    // reference is recorded as an absolute path
+    let reference = match reference {
+        Some(reference) if reference.exists() => Some(fs::canonicalize(reference)?),
+        _ => None,
+    };
 
-    // Initialize and fetch LFS objects after cloning
-    cmd!("git", "lfs", "install").run()?;
-    cmd!("git", "lfs", "fetch", "--all", repo_path).run()?;
+    if source.refspecs.is_empty() {
+        let git = match &reference {
+            Some(reference) => cmd!("git", "clone", "--mirror", "--reference", reference, url, repo_path),
+            None => cmd!("git", "clone", "--mirror", url, repo_path),
+        };
+        with_credentials(git, credentials).run()?;
+    } else {
+        // Only fetch the configured refs instead of cloning everything first
+        cmd!("git", "init", "--bare", repo_path).run()?;
+        if let Some(reference) = &reference {
+            fs::write(repo_path.join("objects/info/alternates"), format!("{}\n", reference.join("objects").display()))?;
+        }
+        cmd!("git", "--git-dir", repo_path, "remote", "add", "--mirror=fetch", "origin", url).run()?;
+        configure_refspecs(repo_path, source)?;
+        with_credentials(cmd!("git", "--git-dir", repo_path, "fetch", "--prune", "origin"), credentials).run()?;
+    }
 
     Ok(())
 }
 
-fn update_repository(repo_path: &str, _repo: &str) -> Result<(), Box<dyn std::error::Error>> {
-    cmd!("git", "--git-dir", repo_path, "fetch", "--all").run()?;
-    
-    // Handle LFS objects after fetching changes
-    cmd!("git", "lfs", "fetch", "--all", repo_path).run()?;
+fn update_repository(repo_path: &Path, source: &SourceConfig, credentials: &[GitCredential]) -> Result<(), Box<dyn std::error::Error>> {
+    configure_refspecs(repo_path, source)?;
+    with_credentials(cmd!("git", "--git-dir", repo_path, "fetch", "--all"), credentials).run()?;
 
     Ok(())
 }
 
-pub fn push_to_gitea(config: &GiteaConfig, repo_path: &str, org_name: &str, repo_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let authenticated_url = format!("http://{}:{}@{}/{}/{}.git", 
-        config.username, 
-        config.password, 
-        config.url.trim_start_matches("http://"),
-        org_name, 
-        repo_name
-    );
-    cmd!("git", "--git-dir", repo_path, "push", "--mirror", authenticated_url).run()?;
+/// Runs `git gc`, which repacks the mirror into a single pack without the
+/// objects of its pool and prunes unreachable objects, then writes a
+/// commit-graph. Mirrors in `borrowed` keep unreachable objects, since
+/// repositories borrowing from them may still need those. Returns the
+/// number of bytes reclaimed.
+pub fn maintain_repository(repo_path: &Path, borrowed: &HashSet<PathBuf>) -> Result<u64, Box<dyn std::error::Error>> {
+    let before = pool::disk_usage(repo_path);
+    let prune = match borrowed.contains(&fs::canonicalize(repo_path)?) {
+        true => "gc.pruneExpire=never",
+        false => "gc.pruneExpire=2.weeks.ago",
+    };
+    cmd!("git", "-c", prune, "--git-dir", repo_path, "gc", "--quiet").run()?;
+    cmd!("git", "--git-dir", repo_path, "commit-graph", "write", "--reachable", "--split").run()?;
+    Ok(before.saturating_sub(pool::disk_usage(repo_path)))
+}
+
+/// Replaces the fetch refspecs of `origin` with the ones configured for the
+/// source, or with the `+refs/*:refs/*` of `git clone --mirror` when none
+/// are, so changes to the configuration apply to existing mirrors too.
+fn configure_refspecs(repo_path: &Path, source: &SourceConfig) -> Result<(), Box<dyn std::error::Error>> {
+    cmd!("git", "--git-dir", repo_path, "config", "--unset-all", "remote.origin.fetch")
+        .unchecked()
+        .run()?;
+    let mirror = ["+refs/*:refs/*".to_string()];
+    let refspecs = if source.refspecs.is_empty() { &mirror[..] } else { &source.refspecs[..] };
+    for refspec in refspecs {
+        cmd!("git", "--git-dir", repo_path, "config", "--add", "remote.origin.fetch", refspec).run()?;
+    }
     Ok(())
 }
@@ -0,0 +1,101 @@
+use crate::catalog;
+use crate::config::BundleConfig;
+use crate::destination::{Destination, PushRequest};
+use crate::metadata;
+use duct::cmd;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes every repository as a single `git bundle` file holding all its
+/// refs, for media such as tapes or USB drives. Each run writes into its own
+/// directory named after the date, such as `<path>/2024-05-01/`:
+///
+/// - `<owner>/<repo>.bundle` and `<owner>/<repo>.wiki.bundle`
+/// - `<owner>/<repo>.lfs.tar`: the LFS objects, when enabled
+/// - `SHA256SUMS`: checksums of every file, as read by `sha256sum -c`
+pub struct BundleDestination {
+    dir: PathBuf,
+    lfs: bool,
+}
+
+impl BundleDestination {
+    pub fn new(config: &BundleConfig) -> Self {
+        BundleDestination {
+            dir: Path::new(&config.path).join(catalog::utc_date(catalog::now())),
+            lfs: config.lfs,
+        }
+    }
+
+    fn write_bundle(&self, repo_path: &Path, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.store(name, |partial| create_bundle(repo_path, partial))
+    }
+
+    /// Writes the file `name` in the directory of this run with `write`, which
+    /// gets a temporary path, and records its checksum.
+    fn store(
+        &self,
+        name: &str,
+        write: impl FnOnce(&Path) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = self.dir.join(name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let partial = PathBuf::from(format!("{}.part", target.display()));
+        write(&partial)?;
+        let sha256 = metadata::sha256_file(&partial)?;
+        fs::rename(&partial, &target)?;
+        self.record_checksum(name, &sha256)
+    }
+
+    fn record_checksum(&self, name: &str, sha256: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.dir.join("SHA256SUMS");
+        let mut sums: BTreeMap<String, String> = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| line.split_once("  "))
+                .map(|(sha256, name)| (name.to_string(), sha256.to_string()))
+                .collect(),
+            Err(_) => BTreeMap::new(),
+        };
+        sums.insert(name.to_string(), sha256.to_string());
+
+        let content: String = sums
+            .iter()
+            .map(|(name, sha256)| format!("{}  {}\n", sha256, name))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+impl Destination for BundleDestination {
+    fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let name = format!("{}/{}", request.owner, request.repo);
+        self.write_bundle(repo_path, &format!("{}.bundle", name))?;
+        if self.lfs && request.lfs {
+            self.store(&format!("{}.lfs.tar", name), |partial| {
+                cmd!("tar", "-cf", partial, "-C", repo_path, "lfs/objects").run()?;
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn push_wiki(&self, wiki_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_bundle(wiki_path, &format!("{}/{}.wiki.bundle", request.owner, request.repo))
+    }
+}
+
+/// Writes a bundle of every ref of the mirror at `repo_path` to `path`.
+pub fn create_bundle(repo_path: &Path, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let refs = cmd!("git", "--git-dir", repo_path, "for-each-ref", "--count=1").read()?;
+    if refs.is_empty() {
+        return Err(format!("{} has no refs to bundle", repo_path.display()).into());
+    }
+    cmd!("git", "--git-dir", repo_path, "bundle", "create", "--quiet", path, "--all").run()?;
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crate::source::{self, RemoteRepo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CATALOG_FILE: &str = "catalog.json";
+
+/// Record of every repository archived under `output_dir` and the outcome of
+/// the last push to each destination, stored as `output_dir/catalog.json`.
+/// Entries are keyed by the mirror's location relative to `output_dir`
+/// without `.git`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Catalog {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub repositories: BTreeMap<String, CatalogEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct CatalogEntry {
+    /// Path of the mirror relative to `output_dir`.
+    pub path: String,
+    #[serde(default)]
+    pub forge: String,
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub clone_url: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gist: bool,
+    /// Whether the repository was archived or private on its forge when it
+    /// was last listed, and its topics, which destinations can reproduce.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<String>,
+    /// Key of the repository this one was mirrored as a fork of, which
+    /// makes the catalog a record of the forks network.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Object pool the repository borrows its objects from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(default)]
+    pub last_fetched: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_maintained: Option<u64>,
+    #[serde(default)]
+    pub destinations: BTreeMap<String, DestinationStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DestinationStatus {
+    #[serde(default)]
+    pub last_pushed: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Fingerprint of the mirror at the last successful push, kept for
+    /// destinations that skip repositories which have not changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+}
+
+impl Catalog {
+    pub fn load(output_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = output_dir.join(CATALOG_FILE);
+        let mut catalog = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Catalog::default()
+        };
+        catalog.path = path;
+        Ok(catalog)
+    }
+
+    /// Writes the catalog through a temporary file so an interrupted run
+    /// never leaves a truncated catalog behind.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn entry(&mut self, repo: &RemoteRepo) -> &mut CatalogEntry {
+        let entry = self.repositories.entry(repo.path.clone()).or_default();
+        entry.path = format!("{}.git", repo.path);
+        entry.forge = repo.forge.clone();
+        entry.owner = repo.owner.clone();
+        entry.name = repo.name.clone();
+        entry.gist = repo.gist;
+        entry.archived = repo.archived;
+        entry.private = repo.private;
+        entry.topics = repo.topics.clone();
+        if !repo.clone_url.is_empty() {
+            entry.clone_url = repo.clone_url.clone();
+        }
+        entry
+    }
+
+    pub fn last_fetched(&self, repo: &RemoteRepo) -> Option<u64> {
+        self.repositories.get(&repo.path).and_then(|entry| entry.last_fetched)
+    }
+
+    /// The key of another archived repository cloned from the same place as
+    /// `repo`, ignoring the protocol, user name and `.git` suffix of the URLs.
+    pub fn duplicate_of(&self, repo: &RemoteRepo) -> Option<&str> {
+        let location = source::from_url(&repo.clone_url).ok()?.path;
+        self.repositories
+            .iter()
+            .find(|(key, entry)| {
+                **key != repo.path && source::from_url(&entry.clone_url).is_ok_and(|other| other.path == location)
+            })
+            .map(|(key, _)| key.as_str())
+    }
+
+    pub fn record_fetch(&mut self, repo: &RemoteRepo) {
+        self.entry(repo).last_fetched = Some(now());
+    }
+
+    /// Whether `interval` has passed since `repo` was last maintained.
+    pub fn maintenance_due(&self, repo: &RemoteRepo, interval: Option<std::time::Duration>) -> bool {
+        let last = self.repositories.get(&repo.path).and_then(|entry| entry.last_maintained);
+        match (interval, last) {
+            (Some(interval), Some(last)) => now().saturating_sub(last) >= interval.as_secs(),
+            _ => true,
+        }
+    }
+
+    pub fn record_maintenance(&mut self, repo: &RemoteRepo) {
+        self.entry(repo).last_maintained = Some(now());
+    }
+
+    /// The fingerprint recorded by the last successful push of `repo` to
+    /// `destination`.
+    pub fn pushed_state(&self, repo: &RemoteRepo, destination: &str) -> Option<&str> {
+        self.repositories.get(&repo.path)?.destinations.get(destination)?.state.as_deref()
+    }
+
+    pub fn record_push(&mut self, repo: &RemoteRepo, destination: &str, result: Result<(), String>, state: Option<String>) {
+        let status = self
+            .entry(repo)
+            .destinations
+            .entry(destination.to_string())
+            .or_default();
+        match result {
+            Ok(()) => {
+                status.last_pushed = Some(now());
+                status.last_error = None;
+                status.state = state;
+            }
+            Err(e) => status.last_error = Some(e),
+        }
+    }
+}
+
+impl CatalogEntry {
+    /// Describes the archived repository as it was last seen on its forge.
+    pub fn repository(&self, key: &str) -> RemoteRepo {
+        RemoteRepo {
+            forge: self.forge.clone(),
+            owner: self.owner.clone(),
+            name: self.name.clone(),
+            path: key.to_string(),
+            clone_url: self.clone_url.clone(),
+            archived: self.archived,
+            private: self.private,
+            topics: self.topics.clone(),
+            gist: self.gist,
+            ..RemoteRepo::default()
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The UTC date `secs` seconds after the Unix epoch, as `YYYY-MM-DD`.
+pub fn utc_date(secs: u64) -> String {
+    // Converts days to a civil date in the proleptic Gregorian calendar, with
+    // eras of 400 years starting on March 1st
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(86399), "1970-01-01");
+        assert_eq!(utc_date(951782400), "2000-02-29");
+        assert_eq!(utc_date(1369353600), "2013-05-24");
+        assert_eq!(utc_date(1709251199), "2024-02-29");
+        assert_eq!(utc_date(4107542400), "2100-03-01");
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::{
+    is_valid_name, parse_repository, Config, DestinationKind, Entry, ForgeConfig, GithubConfig, Overrides, DEFAULT_FORGE,
+};
+use crate::{gitea, github, gitlab, secret};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use toml::Spanned;
+
+/// Positions of the values that are validated beyond what deserialization
+/// checks, so problems can be reported with a line number.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Locations {
+    users: Vec<Spanned<String>>,
+    organizations: Vec<Spanned<String>>,
+    repositories: Vec<Spanned<String>>,
+    starred: Vec<Spanned<String>>,
+    github: Option<Spanned<toml::Value>>,
+    gitea: Option<Spanned<toml::Value>>,
+    dependencies: Option<Spanned<toml::Value>>,
+    forges: BTreeMap<String, Spanned<toml::Value>>,
+    sources: Vec<Spanned<toml::Value>>,
+    destinations: Vec<Spanned<toml::Value>>,
+}
+
+impl Locations {
+    /// Byte offset of the start of an entry of the configuration file.
+    fn start(&self, entry: &Entry) -> Option<usize> {
+        let start = match entry {
+            Entry::Gitea => self.gitea.as_ref()?.span().start,
+            Entry::Forge(name) => self.forges.get(name)?.span().start,
+            Entry::Source(i) => self.sources.get(*i)?.span().start,
+            Entry::Starred(i) => self.starred.get(*i)?.span().start,
+            Entry::Dependencies => self.dependencies.as_ref()?.span().start,
+            Entry::Repository(i) => self.repositories.get(*i)?.span().start,
+            Entry::Destination(i) => self.destinations.get(*i)?.span().start,
+        };
+        Some(start)
+    }
+
+    /// Tables with secret fields, by their path in the file.
+    fn secret_tables(&self) -> Vec<(String, &Spanned<toml::Value>)> {
+        let mut tables = Vec::new();
+        tables.extend(self.github.iter().map(|table| ("github".to_string(), table)));
+        tables.extend(self.gitea.iter().map(|table| ("gitea".to_string(), table)));
+        tables.extend(self.forges.iter().map(|(name, table)| (format!("forges.{}", name), table)));
+        tables.extend(
+            self.destinations
+                .iter()
+                .enumerate()
+                .map(|(i, table)| (format!("destinations[{}]", i), table)),
+        );
+        tables
+    }
+}
+
+struct Problem {
+    line: Option<usize>,
+    message: String,
+}
+
+pub fn execute(config_path: Option<&Path>, overrides: Overrides) -> Result<(), Box<dyn std::error::Error>> {
+    let source = config_path.map_or_else(|| "<defaults>".to_string(), |path| path.display().to_string());
+    let mut problems = Vec::new();
+
+    let content = match config_path {
+        Some(path) => fs::read_to_string(path)?,
+        None => String::new(),
+    };
+
+    let mut locations = toml::from_str::<Locations>(&content).ok();
+    if let Some(locations) = &locations {
+        check_names(&content, locations, &mut problems);
+    }
+
+    let mut config = match toml::from_str::<Config>(&content) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            let start = e.span().map(|span| span.start);
+            problems.push(Problem {
+                line: start.map(|start| line_of(&content, start)),
+                message: e.message().trim().to_string(),
+            });
+            // Deserialization stops at the first secret that cannot be
+            // resolved, so look for the others here
+            if let Some(locations) = &locations {
+                check_secrets(&content, locations, start, &mut problems);
+            }
+            None
+        }
+    };
+
+    if let Some(config) = config.as_mut() {
+        if let Some(locations) = locations.as_mut().filter(|_| overrides.repositories.is_some()) {
+            // The positions no longer match the repositories that are checked
+            locations.repositories.clear();
+        }
+        config.apply(overrides);
+        for error in config.errors() {
+            let start = error.entry.zip(locations.as_ref()).and_then(|(entry, locations)| locations.start(&entry));
+            problems.push(Problem {
+                line: start.map(|start| line_of(&content, start)),
+                message: error.message,
+            });
+        }
+        check_output_dir(config, &mut problems);
+        check_credentials(config, &mut problems);
+    }
+
+    // Repository names are checked both from the file and by `errors`
+    let mut seen = std::collections::HashSet::new();
+    problems.retain(|problem| seen.insert((problem.line, problem.message.clone())));
+
+    if problems.is_empty() {
+        println!("{}: configuration OK", source);
+        return Ok(());
+    }
+
+    problems.sort_by_key(|problem| problem.line.unwrap_or(usize::MAX));
+    for problem in &problems {
+        match problem.line {
+            Some(line) => eprintln!("{}:{}: {}", source, line, problem.message),
+            None => eprintln!("{}: {}", source, problem.message),
+        }
+    }
+    Err(format!("Found {} problem(s) in the configuration", problems.len()).into())
+}
+
+fn check_names(content: &str, locations: &Locations, problems: &mut Vec<Problem>) {
+    for (field, names) in [("users", &locations.users), ("organizations", &locations.organizations)] {
+        for name in names {
+            if !is_valid_name(name.get_ref()) {
+                problems.push(Problem {
+                    line: Some(line_of(content, name.span().start)),
+                    message: format!("{}: '{}' is not a valid name", field, name.get_ref()),
+                });
+            }
+        }
+    }
+
+    for repository in &locations.repositories {
+        if let Err(e) = parse_repository(repository.get_ref()) {
+            problems.push(Problem {
+                line: Some(line_of(content, repository.span().start)),
+                message: format!("repositories: {}", e),
+            });
+        }
+    }
+}
+
+/// Resolves the secrets of every table, skipping the one at `failed` that
+/// deserialization already reported.
+fn check_secrets(content: &str, locations: &Locations, failed: Option<usize>, problems: &mut Vec<Problem>) {
+    for (path, table) in locations.secret_tables() {
+        if failed.is_some_and(|failed| table.span().contains(&failed)) {
+            continue;
+        }
+        for field in ["token", "password", "secret_access_key"] {
+            let value = |suffix: &str| {
+                table
+                    .get_ref()
+                    .get(format!("{}{}", field, suffix))
+                    .and_then(toml::Value::as_str)
+                    .map(str::to_string)
+            };
+            let name = format!("{}.{}", path, field);
+            if let Err(e) = secret::resolve(&name, value(""), value("_file"), value("_command")) {
+                problems.push(Problem {
+                    line: Some(line_of(content, table.span().start)),
+                    message: e,
+                });
+            }
+        }
+    }
+}
+
+fn check_output_dir(config: &Config, problems: &mut Vec<Problem>) {
+    // Walk up to the closest existing directory; that is where the output
+    // directory would be created.
+    let mut dir = Path::new(&config.output_dir);
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+            _ => {
+                dir = Path::new(".");
+                break;
+            }
+        }
+    }
+
+    let probe = dir.join(".archivum-write-check");
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+        }
+        Err(e) => problems.push(Problem {
+            line: None,
+            message: format!(
+                "output_dir '{}' is not writable ({}: {})",
+                config.output_dir,
+                dir.display(),
+                e
+            ),
+        }),
+    }
+}
+
+fn check_credentials(config: &Config, problems: &mut Vec<Problem>) {
+    // GitHub forges that something is archived from, and `[github]` when it
+    // has a token of its own
+    let mut forges: BTreeMap<String, GithubConfig> = BTreeMap::new();
+    if !config.github.token.is_empty() {
+        forges.insert(DEFAULT_FORGE.to_string(), config.github.clone());
+    }
+    let names = config.all_sources().into_iter().map(|source| source.forge);
+    for name in names.chain(config.starred.iter().cloned()) {
+        if let Ok(ForgeConfig::Github(github_config)) = config.forge(&name) {
+            forges.insert(name, github_config);
+        }
+    }
+
+    for (name, github_config) in &forges {
+        let label = if name == DEFAULT_FORGE { "GitHub" } else { name };
+        match github::whoami(github_config) {
+            Ok(login) => println!("{}: authenticated as {}", label, login),
+            Err(e) => problems.push(Problem {
+                line: None,
+                message: format!("{}: authentication failed: {}", name, e.to_string().trim()),
+            }),
+        }
+    }
+
+    for destination in config.all_destinations() {
+        // Only forge destinations have tokens to verify
+        let login = match &destination.kind {
+            DestinationKind::Gitea(gitea_config) => {
+                let (url, token) = (&gitea_config.url, gitea_config.token.expose());
+                gitea::whoami(url, token).map(|login| match gitea::detect_server(url, token) {
+                    Ok(server) => format!("{} on {}", login, server),
+                    Err(_) => login,
+                })
+            }
+            DestinationKind::Gitlab(gitlab_config) => gitlab::whoami(gitlab_config),
+            _ => continue,
+        };
+        match login {
+            Ok(login) => println!("{}: authenticated as {}", destination.name, login),
+            Err(e) => problems.push(Problem {
+                line: None,
+                message: format!("{}: token verification failed: {}", destination.name, e),
+            }),
+        }
+    }
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
@@ -0,0 +1,35 @@
+use crate::catalog::Catalog;
+use crate::config::Config;
+use crate::pool;
+use std::path::Path;
+
+/// Moves the objects of repositories sharing history into shared pools and
+/// reports the space saved.
+pub fn execute(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let output_dir = Path::new(&config.output_dir);
+    let mut catalog = Catalog::load(output_dir)?;
+    let before = pool::disk_usage(output_dir);
+
+    for (id, members) in pool::plan(&catalog, output_dir)? {
+        println!("Pooling {} repositories with root commit {}", members.len(), id);
+        for member in members {
+            let repo_path = output_dir.join(&catalog.repositories[&member].path);
+            match pool::join(output_dir, &id, &member, &repo_path) {
+                Ok(()) => catalog.repositories.get_mut(&member).unwrap().pool = Some(id.clone()),
+                Err(e) => eprintln!("Failed to add {} to pool {}: {}", member, id, e),
+            }
+        }
+        catalog.save()?;
+
+        // Members that joined in earlier runs keep borrowing from the pool
+        // even if joining failed this time, so all of them are refreshed
+        let members = pool::members(&catalog, output_dir).remove(&id).unwrap_or_default();
+        if let Err(e) = pool::gc(output_dir, &id, &members) {
+            eprintln!("Failed to collect garbage in pool {}: {}", id, e);
+        }
+    }
+
+    let after = pool::disk_usage(output_dir);
+    println!("Reclaimed {}", pool::format_size(before.saturating_sub(after)));
+    Ok(())
+}
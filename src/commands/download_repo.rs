@@ -1,13 +1,18 @@
-use crate::actions;
-use clap::ArgMatches;
-use std::path::PathBuf;
+use crate::actions::{self, Context};
+use crate::config::{Config, DEFAULT_FORGE};
+use crate::source;
 
-pub fn execute(sub_matches: &ArgMatches) {
-    let user_or_org = sub_matches.get_one::<String>("user-org").expect("required");
-    let repo_name = sub_matches.get_one::<String>("repo").expect("required");
-    let base_output_dir = sub_matches.get_one::<PathBuf>("basedir").expect("required");
-    let output_dir = format!("{}/{}", base_output_dir.display(), user_or_org);
+/// Downloads `owner/repo` from GitHub with the options configured for it,
+/// leaving the other configured repositories alone.
+pub fn execute(config: &Config, owner: &str, repo: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Processing single repository: {}/{}", owner, repo);
+    let source_config = config.source_for(DEFAULT_FORGE, owner, repo);
+    let source = source::for_forge(config, &source_config.forge)?;
+    let remote = source.metadata(owner, repo).unwrap_or_else(|e| {
+        eprintln!("Could not fetch metadata of {}/{}: {}", owner, repo, e);
+        source.repository(owner, repo)
+    });
 
-    println!("Processing single repository: {}/{}", user_or_org, repo_name);
-    actions::process_repositories(&[repo_name.to_string()], &output_dir, user_or_org, None);
+    let mut ctx = Context::new(config.output_dir.as_ref(), Vec::new())?;
+    actions::process_repositories(&mut ctx, &[remote], &source_config, Some(source.as_ref()))
 }
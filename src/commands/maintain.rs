@@ -0,0 +1,52 @@
+use crate::actions;
+use crate::catalog::Catalog;
+use crate::config::Config;
+use crate::pool;
+use std::path::Path;
+
+/// Runs `git gc` and writes commit-graphs for every archived repository whose
+/// maintenance cadence has passed, then collects garbage in the object pools.
+/// Repositories without a cadence are maintained on every run.
+pub fn execute(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let output_dir = Path::new(&config.output_dir);
+    let mut catalog = Catalog::load(output_dir)?;
+    let borrowed = pool::borrowed(&catalog, output_dir);
+    let mut total = 0;
+
+    let keys: Vec<String> = catalog.repositories.keys().cloned().collect();
+    for key in &keys {
+        let entry = &catalog.repositories[key];
+        let repo = entry.repository(key);
+        let repo_path = output_dir.join(&entry.path);
+        if !repo_path.exists() {
+            continue;
+        }
+        let interval = config.source_for_repository(&repo).maintenance()?;
+        if !catalog.maintenance_due(&repo, interval) {
+            println!("Skipping {}: maintained recently", repo.full_name());
+            continue;
+        }
+
+        match actions::maintain_repository(&repo_path, &borrowed) {
+            Ok(reclaimed) => {
+                println!("Maintained {}, reclaimed {}", repo.full_name(), pool::format_size(reclaimed));
+                total += reclaimed;
+                catalog.record_maintenance(&repo);
+                catalog.save()?;
+            }
+            Err(e) => eprintln!("Failed to maintain {}: {}", repo.full_name(), e),
+        }
+    }
+
+    for (id, members) in pool::members(&catalog, output_dir) {
+        let pool_path = pool::pool_path(output_dir, &id);
+        let before = pool::disk_usage(&pool_path);
+        match pool::gc(output_dir, &id, &members) {
+            Ok(()) => total += before.saturating_sub(pool::disk_usage(&pool_path)),
+            Err(e) => eprintln!("Failed to collect garbage in pool {}: {}", id, e),
+        }
+    }
+
+    println!("Reclaimed {}", pool::format_size(total));
+    Ok(())
+}
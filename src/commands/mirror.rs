@@ -1,115 +1,70 @@
-use crate::config::{Config, GiteaConfig};
-use crate::actions;
-use crate::github::get_repositories;
-use std::path::Path;
-use crate::gitea::{create_org_if_no_conflict, check_repo_exists, create_repo};
+use crate::actions::{self, Context};
+use crate::config::{Config, SourceConfig};
+use crate::destination;
+use crate::metadata::ProfileArchive;
+use crate::source::{self, Source};
 
 pub fn execute(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    let output_dir = Path::new(&config.output_dir);
-
-    // Process users and organizations
-    for user_or_org in config.users.iter().chain(config.organizations.iter()) {
-        process_user_or_org(user_or_org, output_dir, config.gitea.as_ref())?;
-    }
-
-    // Process individual repositories
-    for full_repo_name in &config.repositories {
-        process_individual_repo(full_repo_name, output_dir, config.gitea.as_ref())?;
-    }
-
-    // Create Gitea organizations and repositories if configuration is provided
-    if let Some(gitea_config) = &config.gitea {
-        process_gitea_tasks(config, output_dir, gitea_config)?;
-    }
-
+    let mut ctx = Context::new(config.output_dir.as_ref(), destination::targets(config)?)?;
+    archive(config, &mut ctx)?;
+    ctx.print_summary();
     Ok(())
 }
 
-fn process_user_or_org(
-    user_or_org: &str,
-    output_dir: &Path,
-    gitea_config: Option<&GiteaConfig>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let repos = get_repositories(user_or_org)?;
-    actions::process_repositories(&repos, &output_dir.join(user_or_org).to_string_lossy(), user_or_org, gitea_config)?;
-    Ok(())
-}
-
-fn process_individual_repo(
-    full_repo_name: &str,
-    output_dir: &Path,
-    gitea_config: Option<&GiteaConfig>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let split: Vec<&str> = full_repo_name.split('/').collect();
-    if split.len() == 2 {
-        let user_or_org = split[0];
-        let repo = split[1];
-        actions::process_repositories(&[repo.to_string()], &output_dir.join(user_or_org).to_string_lossy(), user_or_org, gitea_config)?;
-    } else {
-        eprintln!("Invalid repository name format: {}", full_repo_name);
-    }
-    Ok(())
-}
-
-fn process_gitea_tasks(
-    config: &Config,
-    output_dir: &Path,
-    gitea_config: &GiteaConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    for user_or_org in config.users.iter().chain(config.organizations.iter()) {
-        // Check if the organization exists, create if not
-        match create_org_if_no_conflict(&gitea_config.url, &gitea_config.token, user_or_org) {
-            Ok(created) => {
-                if created {
-                    println!("Created organization {} in Gitea.", user_or_org);
-                } else {
-                    println!("Organization {} already exists in Gitea.", user_or_org);
-                }
-            },
-            Err(e) => {
-                eprintln!("Error while checking/creating organization {}: {}", user_or_org, e);
-                // Continue processing even if there's an error with organization creation
-            }
+/// Fetches every configured source into the output directory and pushes
+/// the repositories to the destinations in `ctx`.
+pub fn archive(config: &Config, ctx: &mut Context) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.dependencies = config.dependencies.as_ref().map(|dependencies| dependencies.source());
+    for source_config in config.all_sources() {
+        if let Some(url) = &source_config.url {
+            let repo = source::from_url(url)?;
+            actions::process_repositories(ctx, &[repo], &source_config, None)?;
+            continue;
         }
 
-        let repos = get_repositories(user_or_org)?;
-        for repo in repos {
-            process_gitea_repo(gitea_config, user_or_org, &repo, output_dir)?;
-        }
-    }
-
-    // Process individual repositories for Gitea upload
-    for full_repo_name in &config.repositories {
-        let split: Vec<&str> = full_repo_name.split('/').collect();
-        if split.len() == 2 {
-            let user_or_org = split[0];
-            let repo = split[1];
-            process_gitea_repo(gitea_config, user_or_org, repo, output_dir)?;
+        let source = source::for_forge(config, &source_config.forge)?;
+        match &source_config.repo {
+            Some(repo) => {
+                let repo = source.metadata(&source_config.owner, repo).unwrap_or_else(|e| {
+                    eprintln!("Could not fetch metadata of {}/{}: {}", source_config.owner, repo, e);
+                    source.repository(&source_config.owner, repo)
+                });
+                actions::process_repositories(ctx, &[repo], &source_config, Some(source.as_ref()))?;
+            }
+            None => process_owner(ctx, config, source.as_ref(), &source_config)?,
         }
     }
 
     Ok(())
 }
 
-fn process_gitea_repo(
-    gitea_config: &GiteaConfig,
-    user_or_org: &str,
-    repo: &str,
-    output_dir: &Path,
+fn process_owner(
+    ctx: &mut Context,
+    config: &Config,
+    source: &dyn Source,
+    source_config: &SourceConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Ensure the repository exists within the organization, create if not
-    if !check_repo_exists(&gitea_config.url, &gitea_config.token, user_or_org, repo) {
-        if create_repo(&gitea_config.url, &gitea_config.token, user_or_org, repo) {
-            println!("Repository {}/{} created successfully in Gitea.", user_or_org, repo);
-        } else {
-            return Err(format!("Failed to create repository {}/{} in Gitea.", user_or_org, repo).into());
+    if source_config.profile {
+        let archive = ProfileArchive::new(ctx.output_dir.join(source.local_path(&source_config.owner, ".profile")));
+        if let Err(e) = source.archive_profile(&source_config.owner, &archive) {
+            eprintln!("Could not archive the profile of {}: {}", source_config.owner, e);
         }
     }
 
-    // Push the repository to Gitea
-    let repo_path = output_dir.join(user_or_org).join(format!("{}.git", repo));
-    actions::push_to_gitea(gitea_config, &repo_path.to_string_lossy(), user_or_org, repo)?;
-    println!("Successfully pushed repository {}/{} to Gitea.", user_or_org, repo);
+    if source_config.gists {
+        let gists = source.list_gists(&source_config.owner)?;
+        actions::process_repositories(ctx, &gists, &source_config.for_gists(), None)?;
+    }
 
+    // Repositories with an entry of their own are processed with those options
+    let repos: Vec<_> = source
+        .list_repositories(&source_config.owner)?
+        .into_iter()
+        .filter(|repo| {
+            source_config.accepts(&repo.name)
+                && !config.has_repo_source(source.forge(), &repo.owner, &repo.name)
+        })
+        .collect();
+    actions::process_repositories(ctx, &repos, source_config, Some(source))?;
     Ok(())
 }
@@ -1,34 +1,35 @@
-use crate::actions;
-use crate::github::get_starred_repositories;
+use crate::actions::{self, Context};
 use crate::config::Config;
-use std::path::Path;
+use crate::destination;
+use crate::source;
 
 pub fn execute(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    let output_dir = Path::new(&config.output_dir);
-
-    let starred_repos = get_starred_repositories()?;
-
+    let mut ctx = Context::new(config.output_dir.as_ref(), destination::targets(config)?)?;
     println!("Mirroring starred repositories:");
+    archive(config, &mut ctx)?;
+    ctx.print_summary();
+    Ok(())
+}
+
+/// Fetches the repositories starred on every forge listed in `starred` and
+/// pushes them to the destinations in `ctx`, reporting failures once all are
+/// processed.
+pub fn archive(config: &Config, ctx: &mut Context) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.dependencies = config.dependencies.as_ref().map(|dependencies| dependencies.source());
     let mut errors = Vec::new();
 
-    for full_repo_name in &starred_repos {
-        let split: Vec<&str> = full_repo_name.split('/').collect();
-        if split.len() == 2 {
-            let user_or_org = split[0];
-            let repo = split[1];
-            let repo_output_dir = output_dir.join(user_or_org);
-            match actions::process_repositories(&[repo.to_string()], &repo_output_dir.to_string_lossy(), user_or_org, config.gitea.as_ref()) {
-                Ok(_) => println!("Successfully mirrored {}/{}", user_or_org, repo),
+    for forge in &config.starred {
+        let source = source::for_forge(config, forge)?;
+        for repo in source.list_starred()? {
+            let source_config = config.source_for(forge, &repo.owner, &repo.name);
+            match actions::process_repositories(ctx, std::slice::from_ref(&repo), &source_config, Some(source.as_ref())) {
+                Ok(_) => println!("Successfully mirrored {}", repo.full_name()),
                 Err(e) => {
-                    let error_msg = format!("Error processing repository {}/{}: {}", user_or_org, repo, e);
+                    let error_msg = format!("Error processing repository {}: {}", repo.full_name(), e);
                     eprintln!("{}", error_msg);
                     errors.push(error_msg);
                 }
             }
-        } else {
-            let error_msg = format!("Invalid repository name format: {}", full_repo_name);
-            eprintln!("{}", error_msg);
-            errors.push(error_msg);
         }
     }
 
@@ -1,6 +1,9 @@
+pub mod config_check;
+pub mod dedupe;
 pub mod download;
 pub mod download_repo;
 pub mod download_starred;
+pub mod maintain;
 pub mod mirror;
 pub mod mirror_starred;
 pub mod upload;
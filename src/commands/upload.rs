@@ -1,92 +1,75 @@
-use crate::config::{Config, GiteaConfig};
-use crate::gitea::{check_repo_exists, create_org_if_no_conflict, create_repo};
-use glob::glob;
+use crate::actions::{self, Context};
+use crate::config::{Config, DEFAULT_FORGE};
+use crate::destination;
+use crate::source::RemoteRepo;
+use glob::{glob_with, MatchOptions};
 use std::path::Path;
 
 pub fn execute(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    let output_dir = Path::new(&config.output_dir);
-
-    if let Some(gitea_config) = &config.gitea {
-        process_gitea_tasks(config, output_dir, gitea_config)?;
-    } else {
-        return Err("Gitea configuration is missing".into());
+    let destinations = destination::targets(config)?;
+    if destinations.is_empty() {
+        return Err("No destinations are configured".into());
     }
 
+    let output_dir = Path::new(&config.output_dir);
+    let mut ctx = Context::new(output_dir, destinations)?;
+    upload_all(&mut ctx, config, output_dir)?;
+    ctx.print_summary();
+
     Ok(())
 }
 
-fn process_gitea_tasks(
-    config: &Config,
-    output_dir: &Path,
-    gitea_config: &GiteaConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Glob pattern to match all git repositories in the specified path
-    let pattern = format!("{}/**/*.git", output_dir.display());
-    let repos = glob(&pattern)?;
+fn upload_all(ctx: &mut Context, config: &Config, output_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut repos: Vec<RemoteRepo> = ctx
+        .catalog
+        .repositories
+        .iter()
+        .map(|(key, entry)| entry.repository(key))
+        .collect();
 
-    for entry in repos {
+    // Mirrors created before the catalog existed are GitHub repositories
+    // stored as <owner>/<repo>.git. Directories starting with a dot, such as
+    // the object pools, are not owners.
+    let pattern = format!("{}/*/*.git", output_dir.display());
+    let options = MatchOptions {
+        require_literal_leading_dot: true,
+        ..MatchOptions::new()
+    };
+    for entry in glob_with(&pattern, options)? {
         let repo_path = entry?;
-        let repo_name = repo_path.file_stem().unwrap().to_str().unwrap();
+        let repo_name = repo_path.file_stem().unwrap().to_string_lossy().to_string();
+        // Wikis are pushed together with their repository
+        if repo_name.ends_with(".wiki") {
+            continue;
+        }
         let org_name = repo_path
             .parent()
             .unwrap()
             .file_name()
             .unwrap()
-            .to_str()
-            .unwrap();
-
-        println!("Processing repository: {}/{}", org_name, repo_name);
-
-        // Check if the organization exists, create if not
-        match create_org_if_no_conflict(&gitea_config.url, &gitea_config.token, org_name) {
-            Ok(created) => {
-                if created {
-                    println!("Created organization {} in Gitea.", org_name);
-                } else {
-                    println!("Organization {} already exists in Gitea.", org_name);
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "Error while checking/creating organization {}: {}",
-                    org_name, e
-                );
-                // Continue processing even if there's an error with organization creation
-            }
+            .to_string_lossy()
+            .to_string();
+        let path = format!("{}/{}", org_name, repo_name);
+        if !ctx.catalog.repositories.contains_key(&path) {
+            repos.push(RemoteRepo {
+                forge: DEFAULT_FORGE.to_string(),
+                owner: org_name,
+                name: repo_name,
+                path,
+                ..RemoteRepo::default()
+            });
         }
+    }
 
-        // Ensure the repository exists within the organization, create if not
-        if !check_repo_exists(&gitea_config.url, &gitea_config.token, org_name, repo_name) {
-            if create_repo(&gitea_config.url, &gitea_config.token, org_name, repo_name) {
-                println!(
-                    "Repository {}/{} created successfully in Gitea.",
-                    org_name, repo_name
-                );
-            } else {
-                eprintln!(
-                    "Failed to create repository {}/{} in Gitea.",
-                    org_name, repo_name
-                );
-                continue;
-            }
+    for repo in repos {
+        let repo_path = output_dir.join(format!("{}.git", repo.path));
+        if !repo_path.exists() {
+            continue;
         }
 
-        // Push the repository to Gitea
-        match crate::actions::push_to_gitea(
-            gitea_config,
-            &repo_path.to_string_lossy(),
-            org_name,
-            repo_name,
-        ) {
-            Ok(_) => println!(
-                "Successfully pushed repository {}/{} to Gitea.",
-                org_name, repo_name
-            ),
-            Err(e) => eprintln!(
-                "Failed to push repository {}/{} to Gitea: {}",
-                org_name, repo_name, e
-            ),
-        }
+        println!("Processing repository: {}", repo.full_name());
+        let source = config.source_for_repository(&repo);
+        actions::push_to_destinations(ctx, &repo_path, &source, &repo)?;
     }
 
     Ok(())
@@ -1,22 +1,787 @@
-use serde::Deserialize;
+use crate::git_remote::Location;
+use crate::secret::{self, Secret};
+use crate::source::RemoteRepo;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
+
+/// Directory repositories are mirrored into when neither the configuration
+/// file nor the command line specify one.
+pub const DEFAULT_OUTPUT_DIR: &str = "mirror";
+
+/// Forge used by sources that do not name one. It is configured by the
+/// `[github]` section unless `[forges.github]` is given.
+pub const DEFAULT_FORGE: &str = "github";
+
+/// Forge used for `~user/repo` entries in `repositories`.
+pub const SOURCEHUT_FORGE: &str = "sourcehut";
+
+/// Pseudo forge of repositories configured by their git URL.
+pub const GIT_FORGE: &str = "git";
+
+/// A problem with the configuration, with the entry of the file it is in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub entry: Option<Entry>,
+    pub message: String,
+}
+
+/// An entry of the configuration file. Lists are indexed in file order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// The legacy `[gitea]` section.
+    Gitea,
+    /// `[forges.<name>]`.
+    Forge(String),
+    /// A `[[sources]]` entry.
+    Source(usize),
+    /// An element of `starred`.
+    Starred(usize),
+    /// The `[dependencies]` section.
+    Dependencies,
+    /// An element of `repositories`.
+    Repository(usize),
+    /// A `[[destinations]]` entry.
+    Destination(usize),
+}
 
 #[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub users: Vec<String>,
     pub organizations: Vec<String>,
     pub repositories: Vec<String>,
     pub output_dir: String,
+    pub github: GithubConfig,
     pub gitea: Option<GiteaConfig>,
+    pub destinations: Vec<DestinationConfig>,
+    pub sources: Vec<SourceConfig>,
+    pub forges: BTreeMap<String, ForgeConfig>,
+    /// Forges whose starred repositories `mirror-starred` and
+    /// `download-starred` archive.
+    pub starred: Vec<String>,
+    /// Mirror the git dependencies declared in the manifests of archived
+    /// repositories.
+    pub dependencies: Option<DependenciesConfig>,
+}
+
+/// Options for all repositories of `owner`, or only for `owner/repo` when
+/// `repo` is set, or for the single repository at `url`. Entries for a single
+/// repository take precedence over entries for its owner. `include` and
+/// `exclude` are glob patterns matched against repository names.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceConfig {
+    /// Name of the forge the repositories are listed from.
+    pub forge: String,
+    /// User, organization or, on GitLab, group path such as `group/subgroup`.
+    pub owner: String,
+    pub repo: Option<String>,
+    /// Git URL of a repository on a server without a supported API.
+    pub url: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Fetch Git LFS objects of repositories that track files with LFS and
+    /// push them to destinations.
+    pub lfs: bool,
+    /// Largest total size of new LFS objects fetched for a repository in one
+    /// run, such as `500M` or `2G`. Larger downloads are skipped.
+    pub lfs_max_size: Option<String>,
+    /// Mirror the wiki of repositories that have one as `<repo>.wiki.git`.
+    pub wiki: bool,
+    /// Archive issues, pull requests, labels and milestones as JSON in
+    /// `<repo>.meta/`.
+    pub issues: bool,
+    /// Archive releases and download their assets into `<repo>.meta/`.
+    pub releases: bool,
+    /// Fetch refspecs replacing the default of mirroring every ref.
+    pub refspecs: Vec<String>,
+    /// Archive the owner's profile and avatar in `<owner>/.profile/`.
+    pub profile: bool,
+    /// Owner name used at destinations instead of `owner`.
+    pub destination_owner: Option<String>,
+    /// Mirror the gists of the owner into `<owner>/gists/<id>.git`.
+    pub gists: bool,
+    /// Owner gists are pushed to at destinations, as `<owner>-<id>`. Gists
+    /// are only kept locally when it is not set.
+    pub gists_owner: Option<String>,
+    pub visibility: Visibility,
+    /// Minimum time between fetches, such as `30m`, `6h`, `1d` or `2w`.
+    pub schedule: Option<String>,
+    /// Minimum time between git maintenance runs. When set, mirrors are also
+    /// maintained after fetching once it has passed.
+    pub maintenance: Option<String>,
+    /// Also mirror the forks of each repository.
+    pub include_forks: Option<ForksConfig>,
+    /// Also mirror the repositories referenced as submodules.
+    pub submodules: Option<SubmodulesConfig>,
+}
+
+/// Options for the repositories found as git dependencies in the manifests
+/// of archived repositories, configured as `[dependencies]`. `include` and
+/// `exclude` are glob patterns matched against the `host/path` of the
+/// dependency, such as `github.com/serde-rs/serde`.
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DependenciesConfig {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub lfs: bool,
+    pub destination_owner: Option<String>,
+    pub visibility: Visibility,
+    pub schedule: Option<String>,
+}
+
+impl DependenciesConfig {
+    /// Options the dependencies are mirrored with, like `url` sources.
+    pub fn source(&self) -> SourceConfig {
+        SourceConfig {
+            forge: GIT_FORGE.to_string(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            lfs: self.lfs,
+            destination_owner: self.destination_owner.clone(),
+            visibility: self.visibility,
+            schedule: self.schedule.clone(),
+            ..SourceConfig::default()
+        }
+    }
+}
+
+/// How much of the forks network `include_forks` mirrors.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForksConfig {
+    /// Levels of forks to follow; 1 only takes direct forks, 2 also their
+    /// forks and so on.
+    pub depth: u32,
+    /// Maximum number of forks mirrored per repository.
+    pub max: usize,
+}
+
+impl Default for ForksConfig {
+    fn default() -> Self {
+        ForksConfig { depth: 1, max: 100 }
+    }
+}
+
+/// How far `submodules` follows submodules of submodules.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubmodulesConfig {
+    /// Levels of submodules to follow; 1 only takes the submodules of the
+    /// repository itself, 2 also theirs and so on.
+    pub depth: u32,
+}
+
+impl Default for SubmodulesConfig {
+    fn default() -> Self {
+        SubmodulesConfig { depth: 1 }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    #[default]
+    Private,
+}
+
+/// A server repositories are archived from, configured as
+/// `[forges.<name>]` and selected by `forge = "<name>"` in `[[sources]]`.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ForgeConfig {
+    Github(GithubConfig),
+    Gitlab(GitlabConfig),
+    /// Gitea or a compatible server such as Forgejo or Codeberg. Only `url`
+    /// and `token` are used when listing repositories.
+    Gitea(GiteaConfig),
+    /// SourceHut, where owners are `~user`.
+    Sourcehut(SourcehutConfig),
+    /// Bitbucket Cloud, where owners are workspaces.
+    Bitbucket(BitbucketConfig),
+    /// A self-hosted Bitbucket Server or Data Center, where owners are
+    /// project keys or `~user` for personal repositories.
+    #[serde(rename = "bitbucket-server")]
+    BitbucketServer(#[serde(deserialize_with = "bitbucket_server")] BitbucketConfig),
+}
+
+/// A SourceHut git service, git.sr.ht unless `url` is set. Listing
+/// repositories requires a personal access token.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "RawSourcehutConfig")]
+pub struct SourcehutConfig {
+    pub url: String,
+    pub token: Secret,
+}
+
+/// Credentials for Bitbucket. `password` is an app password on Bitbucket
+/// Cloud; on Bitbucket Server it may also be an HTTP access token, which is
+/// sent as a bearer token when `username` is empty.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "RawBitbucketConfig")]
+pub struct BitbucketConfig {
+    pub url: String,
+    pub username: String,
+    pub password: Secret,
+}
+
+/// A GitLab instance, gitlab.com unless `url` is set.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "RawGitlabConfig")]
+pub struct GitlabConfig {
+    pub url: String,
+    pub token: Secret,
+}
+
+/// A named place archived repositories are pushed to. `include` and `exclude`
+/// are glob patterns matched against `owner/repo`; an empty `include` accepts
+/// every repository.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct DestinationConfig {
+    pub name: String,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Recreate archived issues and pull requests in the destination's
+    /// issue tracker.
+    #[serde(default)]
+    pub issues: bool,
+    /// Recreate archived releases with their assets.
+    #[serde(default)]
+    pub releases: bool,
+    #[serde(flatten)]
+    pub kind: DestinationKind,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DestinationKind {
+    Gitea(GiteaConfig),
+    /// GitLab, gitlab.com unless `url` is set. The token needs the `api`
+    /// scope and is also used to push.
+    Gitlab(GitlabConfig),
+    Bundle(BundleConfig),
+    S3(S3Config),
+    Git(GitRemoteConfig),
+}
+
+/// Any git server, directory or NAS, reached through a URL template in which
+/// `{owner}` and `{repo}` are replaced, such as
+/// `ssh://backup@host/srv/git/{owner}/{repo}.git`.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GitRemoteConfig {
+    pub url: String,
+    /// Push refspecs replacing the default of mirroring every ref.
+    #[serde(default)]
+    pub refspecs: Vec<String>,
+    /// Create missing bare repositories, on a local path or over SSH.
+    #[serde(default)]
+    pub create: bool,
+    /// Also push LFS objects, for servers with an LFS endpoint.
+    #[serde(default)]
+    pub lfs: bool,
 }
 
+/// Writes a `git bundle` of every repository under `path`, in a directory
+/// named after the date of the run.
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BundleConfig {
+    pub path: String,
+    /// Also write a tarball of the LFS objects of each repository.
+    #[serde(default)]
+    pub lfs: bool,
+}
+
+/// A bucket on an S3-compatible object storage, such as AWS S3, MinIO or
+/// Backblaze B2, archives are uploaded to under `prefix`. Objects are
+/// addressed path-style, as `<endpoint>/<bucket>/<key>`.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(try_from = "RawS3Config")]
+pub struct S3Config {
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: Secret,
+    pub format: ArchiveFormat,
+    /// Size of the parts of multipart uploads, used for larger files.
+    pub part_size: u64,
+}
+
+/// How a repository is stored as a single file.
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// A `git bundle` of every ref.
+    #[default]
+    Bundle,
+    /// A tarball of the bare mirror as it is on disk.
+    Mirror,
+}
+
+/// Smallest part of a multipart upload S3 accepts, except for the last.
+const MIN_PART_SIZE: u64 = 5 << 20;
+
+/// Secret fields (`token`, `password`) accept `${ENV_VAR}` interpolation and
+/// can alternatively be given as `<field>_file` or `<field>_command`.
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(try_from = "RawGithubConfig")]
+pub struct GithubConfig {
+    pub token: Secret,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(try_from = "RawGiteaConfig")]
 pub struct GiteaConfig {
     pub url: String,
-    pub token: String,
+    pub token: Secret,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
+    /// Mark repositories archived on their forge as archived, which makes
+    /// them read-only. Forgejo only.
+    pub archive: bool,
+    /// Copy the topics of repositories. Forgejo only.
+    pub topics: bool,
+    /// Create public repositories as pull mirrors of their original, which
+    /// the server syncs at this interval, such as `8h` or `1d`, instead of
+    /// pushing to them. Forgejo only.
+    pub mirror_interval: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawGithubConfig {
+    token: Option<String>,
+    token_file: Option<String>,
+    token_command: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawGitlabConfig {
+    url: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+    token_command: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawSourcehutConfig {
+    url: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+    token_command: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawBitbucketConfig {
+    url: Option<String>,
+    username: String,
+    password: Option<String>,
+    password_file: Option<String>,
+    password_command: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawGiteaConfig {
+    url: String,
+    username: String,
+    token: Option<String>,
+    token_file: Option<String>,
+    token_command: Option<String>,
+    password: Option<String>,
+    password_file: Option<String>,
+    password_command: Option<String>,
+    archive: bool,
+    topics: bool,
+    mirror_interval: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawS3Config {
+    endpoint: String,
+    region: Option<String>,
+    bucket: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: Option<String>,
+    secret_access_key_file: Option<String>,
+    secret_access_key_command: Option<String>,
+    format: ArchiveFormat,
+    part_size: Option<String>,
+}
+
+impl TryFrom<RawGithubConfig> for GithubConfig {
+    type Error = String;
+
+    fn try_from(raw: RawGithubConfig) -> Result<Self, Self::Error> {
+        Ok(GithubConfig {
+            token: secret::resolve("github.token", raw.token, raw.token_file, raw.token_command)?,
+        })
+    }
+}
+
+impl TryFrom<RawGitlabConfig> for GitlabConfig {
+    type Error = String;
+
+    fn try_from(raw: RawGitlabConfig) -> Result<Self, Self::Error> {
+        Ok(GitlabConfig {
+            url: raw
+                .url
+                .unwrap_or_else(|| "https://gitlab.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            token: secret::resolve("gitlab.token", raw.token, raw.token_file, raw.token_command)?,
+        })
+    }
+}
+
+impl Default for GitlabConfig {
+    fn default() -> Self {
+        GitlabConfig {
+            url: "https://gitlab.com".to_string(),
+            token: Secret::default(),
+        }
+    }
+}
+
+impl TryFrom<RawSourcehutConfig> for SourcehutConfig {
+    type Error = String;
+
+    fn try_from(raw: RawSourcehutConfig) -> Result<Self, Self::Error> {
+        Ok(SourcehutConfig {
+            url: raw
+                .url
+                .unwrap_or_else(|| "https://git.sr.ht".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            token: secret::resolve("sourcehut.token", raw.token, raw.token_file, raw.token_command)?,
+        })
+    }
+}
+
+/// Bitbucket Server has no default URL, unlike Bitbucket Cloud.
+fn bitbucket_server<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BitbucketConfig, D::Error> {
+    let raw = RawBitbucketConfig::deserialize(deserializer)?;
+    if raw.url.is_none() {
+        return Err(D::Error::missing_field("url"));
+    }
+    BitbucketConfig::try_from(raw).map_err(D::Error::custom)
+}
+
+impl TryFrom<RawBitbucketConfig> for BitbucketConfig {
+    type Error = String;
+
+    fn try_from(raw: RawBitbucketConfig) -> Result<Self, Self::Error> {
+        Ok(BitbucketConfig {
+            url: raw
+                .url
+                .unwrap_or_else(|| "https://bitbucket.org".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            username: secret::interpolate(&raw.username)?,
+            password: secret::resolve(
+                "bitbucket.password",
+                raw.password,
+                raw.password_file,
+                raw.password_command,
+            )?,
+        })
+    }
+}
+
+impl TryFrom<RawGiteaConfig> for GiteaConfig {
+    type Error = String;
+
+    fn try_from(raw: RawGiteaConfig) -> Result<Self, Self::Error> {
+        Ok(GiteaConfig {
+            url: raw.url,
+            token: secret::resolve("gitea.token", raw.token, raw.token_file, raw.token_command)?,
+            username: secret::interpolate(&raw.username)?,
+            password: secret::resolve(
+                "gitea.password",
+                raw.password,
+                raw.password_file,
+                raw.password_command,
+            )?,
+            archive: raw.archive,
+            topics: raw.topics,
+            mirror_interval: raw.mirror_interval,
+        })
+    }
+}
+
+impl TryFrom<RawS3Config> for S3Config {
+    type Error = String;
+
+    fn try_from(raw: RawS3Config) -> Result<Self, Self::Error> {
+        let part_size = parse_size(raw.part_size.as_deref().unwrap_or("64M"))?;
+        if part_size < MIN_PART_SIZE {
+            return Err("part_size must be at least 5M".to_string());
+        }
+        Ok(S3Config {
+            endpoint: raw.endpoint,
+            region: raw.region.unwrap_or_else(|| "us-east-1".to_string()),
+            bucket: raw.bucket,
+            prefix: raw.prefix,
+            access_key_id: secret::interpolate(&raw.access_key_id)?,
+            secret_access_key: secret::resolve(
+                "secret_access_key",
+                raw.secret_access_key,
+                raw.secret_access_key_file,
+                raw.secret_access_key_command,
+            )?,
+            format: raw.format,
+            part_size,
+        })
+    }
+}
+
+/// Values supplied on the command line or through `ARCHIVUM_*` environment
+/// variables. Anything set here replaces the corresponding value from the
+/// configuration file.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub output_dir: Option<String>,
+    pub users: Option<Vec<String>>,
+    pub organizations: Option<Vec<String>>,
+    pub repositories: Option<Vec<String>>,
+    pub github_token: Option<String>,
+    pub gitea_url: Option<String>,
+    pub gitea_token: Option<String>,
+    pub gitea_username: Option<String>,
+    pub gitea_password: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            users: Vec::new(),
+            organizations: Vec::new(),
+            repositories: Vec::new(),
+            output_dir: DEFAULT_OUTPUT_DIR.to_string(),
+            github: GithubConfig::default(),
+            gitea: None,
+            destinations: Vec::new(),
+            sources: Vec::new(),
+            forges: BTreeMap::new(),
+            starred: vec![DEFAULT_FORGE.to_string()],
+            dependencies: None,
+        }
+    }
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        SourceConfig {
+            forge: DEFAULT_FORGE.to_string(),
+            owner: String::new(),
+            repo: None,
+            url: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            lfs: false,
+            lfs_max_size: None,
+            wiki: false,
+            issues: false,
+            releases: false,
+            refspecs: Vec::new(),
+            profile: false,
+            destination_owner: None,
+            gists: false,
+            gists_owner: None,
+            visibility: Visibility::default(),
+            schedule: None,
+            maintenance: None,
+            include_forks: None,
+            submodules: None,
+        }
+    }
+}
+
+impl SourceConfig {
+    pub fn for_owner(owner: &str) -> Self {
+        SourceConfig {
+            owner: owner.to_string(),
+            ..SourceConfig::default()
+        }
+    }
+
+    pub fn for_url(url: &str) -> Self {
+        SourceConfig {
+            forge: GIT_FORGE.to_string(),
+            url: Some(url.to_string()),
+            ..SourceConfig::default()
+        }
+    }
+
+    pub fn for_repo(forge: &str, owner: &str, repo: &str) -> Self {
+        SourceConfig {
+            forge: forge.to_string(),
+            owner: owner.to_string(),
+            repo: Some(repo.to_string()),
+            ..SourceConfig::default()
+        }
+    }
+
+    /// Returns whether `repo` passes the `include` and `exclude` filters.
+    pub fn accepts(&self, repo: &str) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|p| glob::Pattern::new(p).is_ok_and(|p| p.matches(repo)))
+        };
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+
+    /// Options for the gists of this entry's owner, which have no LFS objects,
+    /// wikis, issues or releases and are always mirrored completely.
+    pub fn for_gists(&self) -> Self {
+        SourceConfig {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            lfs: false,
+            wiki: false,
+            issues: false,
+            releases: false,
+            refspecs: Vec::new(),
+            include_forks: None,
+            submodules: None,
+            ..self.clone()
+        }
+    }
+
+    /// Options for the forks of this entry's repositories, which keep the
+    /// fetch settings but are pushed under their own owner.
+    pub fn for_forks(&self) -> Self {
+        SourceConfig {
+            repo: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            wiki: false,
+            issues: false,
+            releases: false,
+            gists: false,
+            profile: false,
+            refspecs: Vec::new(),
+            destination_owner: None,
+            include_forks: None,
+            submodules: None,
+            ..self.clone()
+        }
+    }
+
+    /// Options for the repositories referenced as submodules, which may be
+    /// hosted anywhere and are cloned from their git URL.
+    pub fn for_submodules(&self) -> Self {
+        SourceConfig {
+            forge: GIT_FORGE.to_string(),
+            owner: String::new(),
+            url: None,
+            ..self.for_forks()
+        }
+    }
+
+    /// The owner name used at destinations for a repository of `owner`.
+    /// The `~` of personal Bitbucket Server projects is dropped. Nested
+    /// paths such as GitLab subgroups are kept; destinations without nested
+    /// owners flatten them.
+    pub fn destination_owner(&self, owner: &str) -> String {
+        match &self.destination_owner {
+            Some(destination_owner) => destination_owner.clone(),
+            None => owner.trim_start_matches('~').to_string(),
+        }
+    }
+
+    fn matches(&self, forge: &str, owner: &str, repo: Option<&str>) -> bool {
+        self.url.is_none() && self.forge == forge && self.owner == owner && self.repo.as_deref() == repo
+    }
+
+    pub fn schedule(&self) -> Result<Option<Duration>, String> {
+        self.schedule.as_deref().map(parse_interval).transpose()
+    }
+
+    pub fn maintenance(&self) -> Result<Option<Duration>, String> {
+        self.maintenance.as_deref().map(parse_interval).transpose()
+    }
+
+    pub fn lfs_max_size(&self) -> Result<Option<u64>, String> {
+        self.lfs_max_size.as_deref().map(parse_size).transpose()
+    }
+
+    /// Every problem with the options of this source.
+    fn errors(&self) -> Vec<String> {
+        let name = match (&self.url, &self.repo) {
+            (Some(url), _) => url.clone(),
+            (None, Some(repo)) => format!("{}/{}", self.owner, repo),
+            (None, None) => self.owner.clone(),
+        };
+        let mut errors = Vec::new();
+        if let Some(url) = &self.url {
+            if !self.owner.is_empty() || self.repo.is_some() {
+                errors.push(format!("Source '{}' cannot set both url and owner or repo", name));
+            }
+            if !is_git_url(url) {
+                errors.push(format!("Source '{}' is not a git URL", name));
+            } else if let Err(e) = crate::source::from_url(url) {
+                errors.push(e);
+            }
+            if self.issues || self.releases {
+                errors.push(format!("Source '{}' cannot archive issues or releases without a forge", name));
+            }
+        } else {
+            let owner = self.owner.strip_prefix('~').unwrap_or(&self.owner);
+            if !owner.split('/').all(is_valid_name) || !self.repo.as_deref().is_none_or(is_valid_name) {
+                errors.push(format!("Source '{}' is not a valid owner or owner/repo", name));
+            }
+        }
+        for pattern in self.include.iter().chain(&self.exclude) {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                errors.push(format!("Invalid filter '{}' for source '{}': {}", pattern, name, e));
+            }
+        }
+        if let Some(owner) = self.destination_owner.as_ref().filter(|owner| !is_valid_owner_path(owner)) {
+            errors.push(format!("Invalid destination owner '{}' for source '{}'", owner, name));
+        }
+        if let Some(owner) = self.gists_owner.as_ref().filter(|owner| !is_valid_name(owner)) {
+            errors.push(format!("Invalid destination owner '{}' for source '{}'", owner, name));
+        }
+        if let Some(forks) = &self.include_forks {
+            if forks.depth == 0 || forks.max == 0 || self.url.is_some() {
+                errors.push(format!("Invalid include_forks for source '{}'", name));
+            }
+        }
+        if self.submodules.is_some_and(|submodules| submodules.depth == 0) {
+            errors.push(format!("Invalid submodules for source '{}'", name));
+        }
+        if (self.gists || self.profile) && (self.repo.is_some() || self.url.is_some()) {
+            errors.push(format!("Source '{}' archives gists or a profile but is not an owner", name));
+        }
+        if let Err(e) = self.schedule() {
+            errors.push(format!("Invalid schedule for source '{}': {}", name, e));
+        }
+        if let Err(e) = self.maintenance() {
+            errors.push(format!("Invalid maintenance interval for source '{}': {}", name, e));
+        }
+        if let Err(e) = self.lfs_max_size() {
+            errors.push(format!("Invalid lfs_max_size for source '{}': {}", name, e));
+        }
+        errors
+    }
 }
 
 impl Config {
@@ -25,6 +790,412 @@ impl Config {
         let config: Config = toml::from_str(&content)?;
         Ok(config)
     }
+
+    /// Builds the effective configuration: the file at `path` (or the defaults
+    /// when there is no file) with `overrides` applied on top.
+    pub fn load(path: Option<&Path>, overrides: Overrides) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply(overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn apply(&mut self, overrides: Overrides) {
+        if let Some(output_dir) = overrides.output_dir {
+            self.output_dir = output_dir;
+        }
+        if let Some(users) = overrides.users {
+            self.users = users;
+        }
+        if let Some(organizations) = overrides.organizations {
+            self.organizations = organizations;
+        }
+        if let Some(repositories) = overrides.repositories {
+            self.repositories = repositories;
+        }
+        if let Some(token) = overrides.github_token {
+            self.github.token = Secret::new(token);
+        }
+
+        let gitea_overrides = [
+            overrides.gitea_url,
+            overrides.gitea_token,
+            overrides.gitea_username,
+            overrides.gitea_password,
+        ];
+        if gitea_overrides.iter().all(Option::is_none) {
+            return;
+        }
+        let [url, token, username, password] = gitea_overrides;
+        let gitea = self.gitea.get_or_insert_with(GiteaConfig::default);
+        if let Some(url) = url {
+            gitea.url = url;
+        }
+        if let Some(token) = token {
+            gitea.token = Secret::new(token);
+        }
+        if let Some(username) = username {
+            gitea.username = username;
+        }
+        if let Some(password) = password {
+            gitea.password = Secret::new(password);
+        }
+    }
+
+    /// Checks what deserialization cannot, such as names, URLs and
+    /// intervals, and fails with every problem found.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let errors = self.errors();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let messages: Vec<String> = errors.iter().map(|error| error.message.clone()).collect();
+        Err(messages.join("\n").into())
+    }
+
+    /// Every problem `validate` reports, with the entry it was found in.
+    pub fn errors(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let mut error = |entry: Option<Entry>, message: String| errors.push(ConfigError { entry, message });
+
+        if self.output_dir.is_empty() {
+            error(None, "output_dir must not be empty".to_string());
+        }
+        if let Some(gitea) = &self.gitea {
+            if gitea.url.is_empty() {
+                error(Some(Entry::Gitea), "gitea.url is required when Gitea is configured".to_string());
+            }
+        }
+
+        for (name, forge) in &self.forges {
+            if matches!(forge, ForgeConfig::Gitea(gitea) if gitea.url.is_empty()) {
+                error(Some(Entry::Forge(name.clone())), format!("Forge '{}' requires a url", name));
+            }
+        }
+        // Other destinations have no nested owners to put the repositories in
+        let gitlab = self.destinations.iter().any(|d| matches!(d.kind, DestinationKind::Gitlab(_)));
+        let nested = |owner: &Option<String>| {
+            owner
+                .as_ref()
+                .filter(|owner| !gitlab && owner.contains('/'))
+                .map(|owner| format!("Destination owner '{}' is nested, which requires a GitLab destination", owner))
+        };
+        for (i, source) in self.sources.iter().enumerate() {
+            for message in source.errors() {
+                error(Some(Entry::Source(i)), message);
+            }
+            if let Some(message) = nested(&source.destination_owner) {
+                error(Some(Entry::Source(i)), message);
+            }
+            if let Err(e) = self.forge(&source.forge) {
+                error(Some(Entry::Source(i)), e);
+            }
+        }
+        for (i, forge) in self.starred.iter().enumerate() {
+            if let Err(e) = self.forge(forge) {
+                error(Some(Entry::Starred(i)), e);
+            }
+        }
+        if let Some(dependencies) = &self.dependencies {
+            let entry = Some(Entry::Dependencies);
+            for pattern in dependencies.include.iter().chain(&dependencies.exclude) {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    error(entry.clone(), format!("Invalid filter '{}' for dependencies: {}", pattern, e));
+                }
+            }
+            if let Some(owner) = dependencies.destination_owner.as_ref().filter(|owner| !is_valid_owner_path(owner)) {
+                error(entry.clone(), format!("Invalid destination owner '{}' for dependencies", owner));
+            }
+            if let Some(message) = nested(&dependencies.destination_owner) {
+                error(entry.clone(), message);
+            }
+            if let Err(e) = dependencies.source().schedule() {
+                error(entry, format!("Invalid schedule for dependencies: {}", e));
+            }
+        }
+        for (i, entry) in self.repositories.iter().enumerate() {
+            let result = parse_repository(entry).and_then(|entry| match entry {
+                RepositoryEntry::Url(url) => crate::source::from_url(url).map(|_| ()),
+                _ => Ok(()),
+            });
+            if let Err(e) = result {
+                error(Some(Entry::Repository(i)), format!("repositories: {}", e));
+            }
+        }
+
+        // The legacy `[gitea]` section comes first and is not an entry of
+        // `[[destinations]]`
+        let destinations = self.all_destinations();
+        let legacy = destinations.len() - self.destinations.len();
+        for (i, destination) in destinations.iter().enumerate() {
+            let entry = if i < legacy { Some(Entry::Gitea) } else { Some(Entry::Destination(i - legacy)) };
+            let name = &destination.name;
+            if destinations[..i].iter().any(|other| other.name == *name) {
+                error(entry.clone(), format!("Destination name '{}' is used more than once", name));
+            }
+            for pattern in destination.include.iter().chain(&destination.exclude) {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    error(entry.clone(), format!("Invalid filter '{}' for destination '{}': {}", pattern, name, e));
+                }
+            }
+            if let DestinationKind::Gitea(gitea) = &destination.kind {
+                if let Some(Err(e)) = gitea.mirror_interval.as_deref().map(parse_interval) {
+                    error(entry.clone(), format!("Invalid mirror_interval for destination '{}': {}", name, e));
+                }
+            }
+            let message = match &destination.kind {
+                DestinationKind::Gitea(gitea) if gitea.url.is_empty() => {
+                    format!("Destination '{}' requires a url", name)
+                }
+                DestinationKind::Gitlab(gitlab) if gitlab.token.is_empty() => {
+                    format!("Destination '{}' requires a token", name)
+                }
+                DestinationKind::Bundle(bundle) if bundle.path.is_empty() => {
+                    format!("Destination '{}' requires a path", name)
+                }
+                DestinationKind::S3(s3) if reqwest::Url::parse(&s3.endpoint).is_err() || s3.bucket.is_empty() => {
+                    format!("Destination '{}' requires an endpoint URL and a bucket", name)
+                }
+                DestinationKind::Git(git) if !git.url.contains("{repo}") => {
+                    format!("The url of destination '{}' must contain {{repo}}", name)
+                }
+                DestinationKind::Git(git) if git.create && matches!(Location::of(&git.url), Location::Other) => {
+                    format!("Destination '{}' can only create repositories on local paths or over SSH", name)
+                }
+                DestinationKind::Gitea(_)
+                | DestinationKind::Gitlab(_)
+                | DestinationKind::Bundle(_)
+                | DestinationKind::S3(_)
+                | DestinationKind::Git(_) => continue,
+            };
+            error(entry, message);
+        }
+        errors
+    }
+
+    /// Every configured source: the `[[sources]]` entries followed by the
+    /// plain `users`, `organizations` and `repositories` lists, skipping list
+    /// entries that already have a `[[sources]]` entry.
+    pub fn all_sources(&self) -> Vec<SourceConfig> {
+        let owners = self.users.iter().chain(&self.organizations).map(|owner| SourceConfig::for_owner(owner));
+        let repos = self
+            .repositories
+            .iter()
+            .filter_map(|entry| parse_repository(entry).ok())
+            .map(|entry| match entry {
+                RepositoryEntry::Github(owner, repo) => SourceConfig::for_repo(DEFAULT_FORGE, owner, repo),
+                RepositoryEntry::Sourcehut(owner, repo) => SourceConfig::for_repo(SOURCEHUT_FORGE, owner, repo),
+                RepositoryEntry::Url(url) => SourceConfig::for_url(url),
+            });
+
+        let mut sources = self.sources.clone();
+        for source in owners.chain(repos) {
+            let configured = self.sources.iter().any(|s| match &source.url {
+                Some(url) => s.url.as_ref() == Some(url),
+                None => s.matches(&source.forge, &source.owner, source.repo.as_deref()),
+            });
+            if !configured {
+                sources.push(source);
+            }
+        }
+        sources
+    }
+
+    /// The options that apply to an archived repository, such as one from
+    /// the catalog. Repositories configured by their git URL are found by
+    /// their location under `output_dir`.
+    pub fn source_for_repository(&self, repo: &RemoteRepo) -> SourceConfig {
+        if repo.forge != GIT_FORGE {
+            return self.source_for(&repo.forge, &repo.owner, &repo.name);
+        }
+        self.all_sources()
+            .into_iter()
+            .find(|s| {
+                s.url
+                    .as_deref()
+                    .and_then(|url| crate::source::from_url(url).ok())
+                    .is_some_and(|configured| configured.path == repo.path)
+            })
+            .unwrap_or_else(|| SourceConfig::for_url(&repo.clone_url))
+    }
+
+    /// The options that apply to `owner/repo` on `forge`: its own
+    /// `[[sources]]` entry, otherwise the entry for its owner, otherwise the
+    /// defaults.
+    pub fn source_for(&self, forge: &str, owner: &str, repo: &str) -> SourceConfig {
+        let sources = self.all_sources();
+        sources
+            .iter()
+            .find(|s| s.matches(forge, owner, Some(repo)))
+            .or_else(|| sources.iter().find(|s| s.matches(forge, owner, None)))
+            .cloned()
+            .unwrap_or_else(|| SourceConfig::for_repo(forge, owner, repo))
+    }
+
+    /// Whether `owner/repo` on `forge` has an entry of its own, in which case
+    /// entries for its owner leave it alone.
+    pub fn has_repo_source(&self, forge: &str, owner: &str, repo: &str) -> bool {
+        self.all_sources()
+            .iter()
+            .any(|s| s.matches(forge, owner, Some(repo)))
+    }
+
+    /// Looks up a forge by name. `github` defaults to the `[github]` section,
+    /// while `gitlab` and `codeberg` default to anonymous access to
+    /// gitlab.com and codeberg.org.
+    pub fn forge(&self, name: &str) -> Result<ForgeConfig, String> {
+        match (self.forges.get(name), name) {
+            (Some(forge), _) => Ok(forge.clone()),
+            (None, "github") => Ok(ForgeConfig::Github(self.github.clone())),
+            (None, "gitlab") => Ok(ForgeConfig::Gitlab(GitlabConfig::default())),
+            (None, "codeberg") => Ok(ForgeConfig::Gitea(GiteaConfig {
+                url: "https://codeberg.org".to_string(),
+                ..GiteaConfig::default()
+            })),
+            (None, "sourcehut") => Ok(ForgeConfig::Sourcehut(SourcehutConfig {
+                url: "https://git.sr.ht".to_string(),
+                token: Secret::default(),
+            })),
+            (None, "bitbucket") => Ok(ForgeConfig::Bitbucket(BitbucketConfig {
+                url: "https://bitbucket.org".to_string(),
+                username: String::new(),
+                password: Secret::default(),
+            })),
+            (None, _) => Err(format!("Unknown forge '{}'", name)),
+        }
+    }
+
+    /// Every configured destination, including the legacy `[gitea]` section
+    /// which is exposed as a destination named `gitea`.
+    pub fn all_destinations(&self) -> Vec<DestinationConfig> {
+        let legacy = self.gitea.iter().map(|gitea| DestinationConfig {
+            name: "gitea".to_string(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            issues: false,
+            releases: false,
+            kind: DestinationKind::Gitea(gitea.clone()),
+        });
+        legacy.chain(self.destinations.iter().cloned()).collect()
+    }
+}
+
+/// An entry of `repositories`.
+#[derive(Debug, PartialEq)]
+pub enum RepositoryEntry<'a> {
+    /// `owner/repo` on GitHub.
+    Github(&'a str, &'a str),
+    /// `~user/repo` on SourceHut.
+    Sourcehut(&'a str, &'a str),
+    /// A git URL such as `https://host/path.git` or `git@host:path.git`.
+    Url(&'a str),
+}
+
+pub fn parse_repository(entry: &str) -> Result<RepositoryEntry<'_>, String> {
+    if is_git_url(entry) {
+        return Ok(RepositoryEntry::Url(entry));
+    }
+    if let Some(user) = entry.strip_prefix('~') {
+        let (_, repo) = split_repository(user)
+            .map_err(|_| format!("'{}' is not in ~user/repo format", entry))?;
+        let owner = &entry[..entry.len() - repo.len() - 1];
+        return Ok(RepositoryEntry::Sourcehut(owner, repo));
+    }
+    let (owner, repo) = split_repository(entry)?;
+    Ok(RepositoryEntry::Github(owner, repo))
+}
+
+/// Returns whether `url` is a URL git can clone from: `https://`, `http://`,
+/// `ssh://`, `git://`, `file://` or scp-like `user@host:path`.
+pub fn is_git_url(url: &str) -> bool {
+    if let Some((scheme, rest)) = url.split_once("://") {
+        return matches!(scheme, "https" | "http" | "ssh" | "git" | "file")
+            && rest.split_once('/').is_some_and(|(_, path)| !path.is_empty());
+    }
+    match url.split_once(':') {
+        Some((user_host, path)) => {
+            user_host.contains('@') && !user_host.contains('/') && !path.is_empty()
+        }
+        None => false,
+    }
+}
+
+/// Splits an `owner/repo` entry from `repositories`, rejecting anything that
+/// is not a valid GitHub repository name.
+pub fn split_repository(full_name: &str) -> Result<(&str, &str), String> {
+    let (owner, repo) = full_name
+        .split_once('/')
+        .ok_or_else(|| format!("'{}' is not in owner/repo format", full_name))?;
+    if !is_valid_name(owner) || !is_valid_name(repo) || repo.contains('/') {
+        return Err(format!("'{}' is not in owner/repo format", full_name));
+    }
+    Ok((owner, repo))
+}
+
+/// Returns whether `name` only contains characters GitHub allows in user,
+/// organization and repository names.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Returns whether `owner` is a name or a nested path of names such as
+/// `group/subgroup`, which only GitLab destinations keep nested.
+fn is_valid_owner_path(owner: &str) -> bool {
+    owner.split('/').all(is_valid_name)
+}
+
+/// Parses an interval such as `45s`, `30m`, `6h`, `1d` or `2w`.
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not an interval like 6h or 1d", interval))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("'{}' is not an interval like 6h or 1d", interval)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too long", interval))
+}
+
+/// Parses a size in bytes such as `4096`, `512K`, `500M`, `2G` or `1T`, with
+/// binary units.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a size like 500M or 2G", size))?;
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("'{}' is not a size like 500M or 2G", size)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("'{}' is too large", size))
 }
 
 #[cfg(test)]
@@ -42,7 +1213,7 @@ mod tests {
             output_dir = "/tmp/output"
 
             [gitea]
-            api_url = "https://gitea.example.com"
+            url = "https://gitea.example.com"
             token = "abcdef123456"
             username = "testuser"
             password = "testpassword"
@@ -59,9 +1230,10 @@ mod tests {
         assert_eq!(config.output_dir, "/tmp/output");
         assert_eq!(config.gitea, Some(GiteaConfig {
             url: "https://gitea.example.com".to_string(),
-            token: "abcdef123456".to_string(),
+            token: Secret::new("abcdef123456"),
             username: "testuser".to_string(),
-            password: "testpassword".to_string(),
+            password: Secret::new("testpassword"),
+            ..GiteaConfig::default()
         }));
     }
 
@@ -101,4 +1273,462 @@ mod tests {
         let result = Config::from_file(temp_file.path());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_config_defaults_when_fields_missing() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "users = [\"user1\"]").unwrap();
+
+        let config = Config::from_file(temp_file.path()).unwrap();
+
+        assert_eq!(config.users, vec!["user1"]);
+        assert!(config.organizations.is_empty());
+        assert!(config.repositories.is_empty());
+        assert_eq!(config.output_dir, DEFAULT_OUTPUT_DIR);
+        assert_eq!(config.gitea, None);
+    }
+
+    #[test]
+    fn test_config_overrides_replace_file_values() {
+        let mut config = Config {
+            users: vec!["user1".to_string()],
+            organizations: vec!["org1".to_string()],
+            ..Config::default()
+        };
+
+        config.apply(Overrides {
+            output_dir: Some("/srv/archive".to_string()),
+            users: Some(vec!["user2".to_string()]),
+            gitea_url: Some("http://localhost:8086".to_string()),
+            ..Overrides::default()
+        });
+
+        assert_eq!(config.output_dir, "/srv/archive");
+        assert_eq!(config.users, vec!["user2"]);
+        assert_eq!(config.organizations, vec!["org1"]);
+        assert_eq!(config.gitea.unwrap().url, "http://localhost:8086");
+    }
+
+    #[test]
+    fn test_config_load_without_file_requires_gitea_url() {
+        let result = Config::load(None, Overrides {
+            gitea_token: Some("abcdef123456".to_string()),
+            ..Overrides::default()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_secrets_from_env_and_file() {
+        std::env::set_var("ARCHIVUM_TEST_GITEA_TOKEN", "from-env");
+        let mut password_file = NamedTempFile::new().unwrap();
+        writeln!(password_file, "from-file").unwrap();
+
+        let config_content = format!(
+            r#"
+            [github]
+            token_command = "echo from-command"
+
+            [gitea]
+            url = "https://gitea.example.com"
+            token = "${{ARCHIVUM_TEST_GITEA_TOKEN}}"
+            username = "testuser"
+            password_file = "{}"
+            "#,
+            password_file.path().display()
+        );
+        let config: Config = toml::from_str(&config_content).unwrap();
+        let gitea = config.gitea.as_ref().unwrap();
+
+        assert_eq!(config.github.token.expose(), "from-command");
+        assert_eq!(gitea.token.expose(), "from-env");
+        assert_eq!(gitea.password.expose(), "from-file");
+
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("from-command"));
+        assert!(!debug.contains("from-env"));
+        assert!(!debug.contains("from-file"));
+    }
+
+    #[test]
+    fn test_config_secret_rejects_multiple_sources() {
+        let config_content = r#"
+            [gitea]
+            url = "https://gitea.example.com"
+            token = "abcdef123456"
+            token_file = "/run/secrets/gitea_token"
+        "#;
+
+        assert!(toml::from_str::<Config>(config_content).is_err());
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_fields() {
+        let config_content = r#"
+            output_dir = "/tmp/output"
+
+            [gitea]
+            api_url = "https://gitea.example.com"
+        "#;
+
+        let error = toml::from_str::<Config>(config_content).unwrap_err();
+        assert!(error.to_string().contains("api_url"));
+    }
+
+    #[test]
+    fn test_split_repository() {
+        assert_eq!(split_repository("owner/repo"), Ok(("owner", "repo")));
+        assert_eq!(split_repository("my-org/repo.rs"), Ok(("my-org", "repo.rs")));
+        assert!(split_repository("repo").is_err());
+        assert!(split_repository("owner/").is_err());
+        assert!(split_repository("owner/repo/extra").is_err());
+        assert!(split_repository("owner/re po").is_err());
+    }
+
+    #[test]
+    fn test_config_multiple_destinations() {
+        let config_content = r#"
+            [gitea]
+            url = "https://gitea.example.com"
+
+            [[destinations]]
+            name = "eu"
+            type = "gitea"
+            url = "https://gitea.eu.example.com"
+            token = "eu-token"
+            include = ["rust-lang/*"]
+            issues = true
+            releases = true
+
+            [[destinations]]
+            name = "us"
+            type = "gitea"
+            url = "https://forgejo.us.example.com"
+            archive = true
+            topics = true
+            mirror_interval = "8h"
+
+            [[destinations]]
+            name = "tape"
+            type = "bundle"
+            path = "/mnt/tape"
+            lfs = true
+
+            [[destinations]]
+            name = "offsite"
+            type = "s3"
+            endpoint = "http://localhost:9000"
+            bucket = "archive"
+            access_key_id = "minio"
+            secret_access_key = "minio-secret"
+            part_size = "16M"
+
+            [[destinations]]
+            name = "nas"
+            type = "git"
+            url = "backup@nas:/srv/git/{owner}/{repo}.git"
+            refspecs = ["+refs/heads/*:refs/heads/*"]
+            create = true
+
+            [[destinations]]
+            name = "team"
+            type = "gitlab"
+            url = "https://gitlab.example.com/"
+            token = "glpat-token"
+            issues = true
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        config.validate().unwrap();
+        let destinations = config.all_destinations();
+
+        let names: Vec<&str> = destinations.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["gitea", "eu", "us", "tape", "offsite", "nas", "team"]);
+        assert_eq!(destinations[1].include, vec!["rust-lang/*"]);
+        assert!(destinations[1].issues);
+        assert!(destinations[1].releases);
+        assert!(!destinations[2].issues);
+        let DestinationKind::Gitea(eu) = &destinations[1].kind else {
+            panic!("eu is not a Gitea destination");
+        };
+        assert_eq!(eu.url, "https://gitea.eu.example.com");
+        assert_eq!(eu.token.expose(), "eu-token");
+        assert!(!eu.archive);
+        assert_eq!(eu.mirror_interval, None);
+        let DestinationKind::Gitea(us) = &destinations[2].kind else {
+            panic!("us is not a Gitea destination");
+        };
+        assert!(us.archive);
+        assert!(us.topics);
+        assert_eq!(us.mirror_interval.as_deref(), Some("8h"));
+        assert_eq!(
+            destinations[3].kind,
+            DestinationKind::Bundle(BundleConfig {
+                path: "/mnt/tape".to_string(),
+                lfs: true,
+            })
+        );
+        let DestinationKind::S3(offsite) = &destinations[4].kind else {
+            panic!("offsite is not an S3 destination");
+        };
+        assert_eq!(offsite.region, "us-east-1");
+        assert_eq!(offsite.format, ArchiveFormat::Bundle);
+        assert_eq!(offsite.part_size, 16 << 20);
+        assert_eq!(offsite.secret_access_key.expose(), "minio-secret");
+        let DestinationKind::Git(nas) = &destinations[5].kind else {
+            panic!("nas is not a git destination");
+        };
+        assert_eq!(nas.url_for("rust-lang", "rust"), "backup@nas:/srv/git/rust-lang/rust.git");
+        assert!(nas.create);
+        let DestinationKind::Gitlab(team) = &destinations[6].kind else {
+            panic!("team is not a GitLab destination");
+        };
+        assert_eq!(team.url, "https://gitlab.example.com");
+        assert_eq!(team.token.expose(), "glpat-token");
+    }
+
+    #[test]
+    fn test_config_rejects_duplicate_destination_names() {
+        let config_content = r#"
+            [[destinations]]
+            name = "backup"
+            type = "gitea"
+            url = "https://a.example.com"
+
+            [[destinations]]
+            name = "backup"
+            type = "gitea"
+            url = "https://b.example.com"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_reports_every_error() {
+        let config_content = r#"
+            repositories = ["octocat/hello", "not-a-repo"]
+
+            [[sources]]
+            owner = "octocat"
+            forge = "nowhere"
+            include = ["[a"]
+
+            [[destinations]]
+            name = "backup"
+            type = "bundle"
+            path = ""
+
+            [[destinations]]
+            name = "backup"
+            type = "bundle"
+            path = "/srv/bundles"
+
+            [[destinations]]
+            name = "forgejo"
+            type = "gitea"
+            url = "https://forgejo.example.com"
+            mirror_interval = "8 hours"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        let entries: Vec<Option<Entry>> = config.errors().into_iter().map(|error| error.entry).collect();
+        assert_eq!(
+            entries,
+            vec![
+                Some(Entry::Source(0)),
+                Some(Entry::Source(0)),
+                Some(Entry::Repository(1)),
+                Some(Entry::Destination(0)),
+                Some(Entry::Destination(1)),
+                Some(Entry::Destination(2)),
+            ]
+        );
+        assert_eq!(config.validate().unwrap_err().to_string().lines().count(), 6);
+    }
+
+    #[test]
+    fn test_config_sources_override_lists() {
+        let config_content = r#"
+            organizations = ["rust-lang", "tokio-rs"]
+            repositories = ["rust-lang/rust"]
+
+            [[sources]]
+            owner = "rust-lang"
+            include = ["rust*"]
+            lfs = true
+            lfs_max_size = "2G"
+            destination_owner = "rust-mirror"
+            visibility = "public"
+            gists = true
+            gists_owner = "rust-gists"
+
+            [[sources]]
+            owner = "rust-lang"
+            repo = "rust"
+            refspecs = ["+refs/heads/master:refs/heads/master"]
+            schedule = "1d"
+            include_forks = { depth = 2 }
+            submodules = {}
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.all_sources().len(), 3);
+
+        let owner = config.source_for("github", "rust-lang", "rustup");
+        assert!(owner.lfs);
+        assert_eq!(owner.lfs_max_size(), Ok(Some(2 << 30)));
+        assert!(owner.accepts("rustup"));
+        assert!(!owner.accepts("cargo"));
+        assert_eq!(owner.destination_owner("rust-lang"), "rust-mirror");
+        assert_eq!(owner.visibility, Visibility::Public);
+        assert!(owner.gists);
+        let gists = owner.for_gists();
+        assert!(!gists.lfs && gists.include.is_empty());
+        assert_eq!(gists.gists_owner.as_deref(), Some("rust-gists"));
+
+        let repo = config.source_for("github", "rust-lang", "rust");
+        assert!(!repo.lfs);
+        assert_eq!(repo.refspecs, vec!["+refs/heads/master:refs/heads/master"]);
+        assert_eq!(repo.schedule(), Ok(Some(Duration::from_secs(24 * 60 * 60))));
+        assert_eq!(repo.include_forks, Some(ForksConfig { depth: 2, max: 100 }));
+        let forks = repo.for_forks();
+        assert!(forks.include_forks.is_none() && forks.refspecs.is_empty());
+        assert_eq!(repo.submodules, Some(SubmodulesConfig { depth: 1 }));
+        let submodules = repo.for_submodules();
+        assert_eq!(submodules.forge, GIT_FORGE);
+        assert!(submodules.submodules.is_none() && submodules.repo.is_none());
+        assert_eq!(forks.schedule, repo.schedule);
+        assert!(config.has_repo_source("github", "rust-lang", "rust"));
+        assert!(!config.has_repo_source("gitlab", "rust-lang", "rust"));
+
+        let default = config.source_for("github", "tokio-rs", "tokio");
+        assert!(!default.lfs);
+        assert_eq!(default.destination_owner("tokio-rs"), "tokio-rs");
+        assert_eq!(default.visibility, Visibility::Private);
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_interval("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert!(parse_interval("soon").is_err());
+        assert!(parse_interval("5y").is_err());
+        assert_eq!(
+            parse_interval("18446744073709551615w"),
+            Err("'18446744073709551615w' is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn test_config_dependencies() {
+        let config_content = r#"
+            [dependencies]
+            include = ["github.com/*"]
+            exclude = ["github.com/rust-lang/*"]
+            destination_owner = "deps"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        config.validate().unwrap();
+        let source = config.dependencies.unwrap().source();
+        assert_eq!(source.forge, GIT_FORGE);
+        assert!(source.accepts("github.com/serde-rs/serde"));
+        assert!(!source.accepts("github.com/rust-lang/log"));
+        assert!(!source.accepts("gitlab.com/group/project"));
+        assert_eq!(source.destination_owner("serde-rs"), "deps");
+
+        let config: Config = toml::from_str("[dependencies]\nschedule = \"soon\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_forges() {
+        let config_content = r#"
+            starred = ["github", "work"]
+
+            [forges.work]
+            type = "gitlab"
+            url = "https://gitlab.example.com/"
+            token = "glpat-123"
+
+            [[sources]]
+            forge = "work"
+            owner = "platform/tools"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(
+            config.forge("work"),
+            Ok(ForgeConfig::Gitlab(GitlabConfig {
+                url: "https://gitlab.example.com".to_string(),
+                token: Secret::new("glpat-123"),
+            }))
+        );
+        assert_eq!(config.forge("gitlab"), Ok(ForgeConfig::Gitlab(GitlabConfig::default())));
+        assert!(config.forge("unknown").is_err());
+        assert!(matches!(
+            config.forge("codeberg"),
+            Ok(ForgeConfig::Gitea(GiteaConfig { url, .. })) if url == "https://codeberg.org"
+        ));
+        assert_eq!(config.sources[0].destination_owner("platform/tools/cli"), "platform/tools/cli");
+    }
+
+    #[test]
+    fn test_config_bitbucket_forges() {
+        let config_content = r#"
+            [forges.vendor]
+            type = "bitbucket"
+            username = "archivist"
+            password = "app-password"
+
+            [forges.corp]
+            type = "bitbucket-server"
+            url = "https://bitbucket.corp.example.com/"
+            password = "access-token"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        let Ok(ForgeConfig::Bitbucket(vendor)) = config.forge("vendor") else {
+            panic!("expected a Bitbucket Cloud forge");
+        };
+        assert_eq!(vendor.url, "https://bitbucket.org");
+        assert_eq!(vendor.username, "archivist");
+
+        let Ok(ForgeConfig::BitbucketServer(corp)) = config.forge("corp") else {
+            panic!("expected a Bitbucket Server forge");
+        };
+        assert_eq!(corp.url, "https://bitbucket.corp.example.com");
+        assert_eq!(corp.password.expose(), "access-token");
+
+        let without_url = r#"
+            [forges.corp]
+            type = "bitbucket-server"
+            password = "access-token"
+        "#;
+        assert!(toml::from_str::<Config>(without_url).is_err());
+    }
+
+    #[test]
+    fn test_parse_repository() {
+        assert_eq!(parse_repository("owner/repo"), Ok(RepositoryEntry::Github("owner", "repo")));
+        assert_eq!(parse_repository("~sircmpwn/scdoc"), Ok(RepositoryEntry::Sourcehut("~sircmpwn", "scdoc")));
+        for url in [
+            "https://git.kernel.org/pub/scm/git/git.git",
+            "ssh://git@example.com:2222/srv/repo.git",
+            "git://git.example.org/project",
+            "git@example.com:team/project.git",
+        ] {
+            assert_eq!(parse_repository(url), Ok(RepositoryEntry::Url(url)));
+        }
+        assert!(parse_repository("ftp://example.com/repo.git").is_err());
+        assert!(parse_repository("https://example.com").is_err());
+        assert!(parse_repository("~user").is_err());
+    }
 }
@@ -0,0 +1,285 @@
+use duct::cmd;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Hosts whose repositories Go module paths and shorthands such as
+/// `github:user/repo` name directly.
+const KNOWN_HOSTS: [&str; 5] = ["github.com", "gitlab.com", "bitbucket.org", "codeberg.org", "git.sr.ht"];
+
+/// The git URLs of the dependencies declared in the manifests on the default
+/// branch of the mirror at `repo_path`. Manifests of vendored code are
+/// skipped.
+pub fn urls(repo_path: &Path) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    // Empty repositories have no default branch
+    let files = cmd!("git", "--git-dir", repo_path, "ls-tree", "-r", "-z", "--name-only", "HEAD")
+        .stderr_null()
+        .unchecked()
+        .read()?;
+
+    let mut urls = BTreeSet::new();
+    for path in files.split('\0').filter(|path| !path.is_empty()) {
+        if path.split('/').any(|segment| segment == "node_modules" || segment == "vendor") {
+            continue;
+        }
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let parse: fn(&str) -> Vec<String> = match name {
+            "Cargo.toml" => cargo,
+            "go.mod" => go_mod,
+            "package.json" => package_json,
+            "Gemfile" => gemfile,
+            _ if name.starts_with("requirements") && name.ends_with(".txt") => requirements,
+            _ => continue,
+        };
+        let manifest = cmd!("git", "--git-dir", repo_path, "cat-file", "blob", format!("HEAD:{}", path)).read()?;
+        urls.extend(parse(&manifest).iter().map(|url| normalize(url)));
+    }
+    Ok(urls)
+}
+
+/// Strips what package managers add to git URLs: a `git+` prefix, a
+/// `#<ref>` fragment and a trailing slash.
+fn normalize(url: &str) -> String {
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    let url = url.split('#').next().unwrap_or(url);
+    url.trim_end_matches('/').to_string()
+}
+
+/// Every `git = "..."` key of `Cargo.toml`, in any dependency or `[patch]`
+/// table.
+fn cargo(manifest: &str) -> Vec<String> {
+    fn collect(value: &toml::Value, urls: &mut Vec<String>) {
+        if let Some(table) = value.as_table() {
+            if let Some(git) = table.get("git").and_then(|git| git.as_str()) {
+                urls.push(git.to_string());
+            }
+            table.values().for_each(|value| collect(value, urls));
+        }
+    }
+    let mut urls = Vec::new();
+    if let Ok(value) = manifest.parse::<toml::Value>() {
+        collect(&value, &mut urls);
+    }
+    urls
+}
+
+/// Modules on known hosts required or used as replacements in `go.mod`.
+/// Other module paths need a request to find their repository.
+fn go_mod(manifest: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut block = "";
+    for line in manifest.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let mut words = line.split_whitespace().peekable();
+        let directive = match (block, words.peek()) {
+            ("", Some(&word)) => {
+                words.next();
+                if words.peek() == Some(&"(") {
+                    block = word;
+                    continue;
+                }
+                word
+            }
+            (_, Some(&")")) => {
+                block = "";
+                continue;
+            }
+            (block, _) => block,
+        };
+        if directive != "require" && directive != "replace" {
+            continue;
+        }
+        for word in words {
+            let segments: Vec<&str> = word.split('/').collect();
+            if segments.len() >= 3 && KNOWN_HOSTS.contains(&segments[0]) {
+                urls.push(format!("https://{}", segments[..3].join("/")));
+            }
+        }
+    }
+    urls
+}
+
+/// Dependencies of `package.json` given as git URLs or as shorthands such as
+/// `user/repo` or `gitlab:user/repo`.
+fn package_json(manifest: &str) -> Vec<String> {
+    let Ok(package) = serde_json::from_str::<Value>(manifest) else {
+        return Vec::new();
+    };
+    let shorthands = [("github:", "github.com"), ("gitlab:", "gitlab.com"), ("bitbucket:", "bitbucket.org")];
+    ["dependencies", "devDependencies", "optionalDependencies", "peerDependencies"]
+        .iter()
+        .filter_map(|key| package[key].as_object())
+        .flat_map(|dependencies| dependencies.values())
+        .filter_map(|spec| {
+            let spec = spec.as_str()?.trim();
+            if spec.starts_with("git+") || spec.starts_with("git://") {
+                return Some(spec.to_string());
+            }
+            if let Some((host, path)) = shorthands
+                .iter()
+                .find_map(|(prefix, host)| Some((host, spec.strip_prefix(prefix)?)))
+            {
+                return Some(format!("https://{}/{}", host, path));
+            }
+            // Plain `user/repo` is a GitHub shorthand; versions, paths and
+            // aliases never look like one
+            let shorthand = spec.split('#').next().unwrap_or(spec);
+            (shorthand.split('/').count() == 2
+                && !shorthand.contains([':', ' '])
+                && !shorthand.starts_with(['.', '/', '~', '@']))
+            .then(|| format!("https://github.com/{}", spec))
+        })
+        .collect()
+}
+
+/// `git+` requirements in pip requirement files, with the `@<ref>` after the
+/// repository path removed.
+fn requirements(manifest: &str) -> Vec<String> {
+    manifest
+        .lines()
+        .filter_map(|line| {
+            let start = line.find("git+")?;
+            let url = line[start..].split_whitespace().next()?;
+            let url = url.split('#').next().unwrap_or(url);
+            let path_start = url.find("://").map_or(0, |i| i + 3);
+            let path_start = path_start + url[path_start..].find('/')?;
+            let url = match url[path_start..].rsplit_once('@') {
+                Some((path, _)) => &url[..path_start + path.len()],
+                None => url,
+            };
+            Some(url.to_string())
+        })
+        .collect()
+}
+
+/// Gems declared with `git:` or `github:` in a `Gemfile`.
+fn gemfile(manifest: &str) -> Vec<String> {
+    manifest
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            if let Some(url) = quoted_after(line, "git:").or_else(|| quoted_after(line, ":git =>")) {
+                return Some(url.to_string());
+            }
+            let repo = quoted_after(line, "github:").or_else(|| quoted_after(line, ":github =>"))?;
+            Some(format!("https://github.com/{}", repo))
+        })
+        .collect()
+}
+
+/// The quoted string following `key` in `line`.
+fn quoted_after<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let rest = line[line.find(key)? + key.len()..].trim_start();
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    rest[1..].split(quote).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_go_mod() {
+        let manifest = "module github.com/octocat/app
+
+go 1.21
+
+require github.com/a/b v1.0.0
+
+require (
+\tgithub.com/c/d/v2 v2.1.0
+\tgolang.org/x/text v0.3.0 // indirect
+\tgitlab.com/e/f v1.0.0
+)
+
+replace github.com/a/b => github.com/fork/b v1.0.1
+
+replace (
+\texample.com/old => codeberg.org/g/h v0.1.0
+)
+";
+        assert_eq!(
+            go_mod(manifest),
+            vec![
+                "https://github.com/a/b",
+                "https://github.com/c/d",
+                "https://gitlab.com/e/f",
+                "https://github.com/a/b",
+                "https://github.com/fork/b",
+                "https://codeberg.org/g/h",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_package_json() {
+        let manifest = r#"{
+            "dependencies": {
+                "plain": "^1.2.3",
+                "range": ">= 1.0.0 < 2",
+                "alias": "npm:other@^2.0.0",
+                "scoped-alias": "npm:@scope/other@1.0.0",
+                "local": "file:../local",
+                "url": "git+ssh://git@example.com/team/url.git#v1",
+                "shorthand": "octocat/shorthand#main"
+            },
+            "devDependencies": {
+                "github": "github:octocat/github",
+                "gitlab": "gitlab:group/gitlab",
+                "relative": "./vendor/relative"
+            }
+        }"#;
+        let urls: Vec<String> = package_json(manifest).iter().map(|url| normalize(url)).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://github.com/octocat/shorthand",
+                "ssh://git@example.com/team/url.git",
+                "https://github.com/octocat/github",
+                "https://gitlab.com/group/gitlab",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requirements() {
+        let manifest = "requests==2.31.0
+# a comment
+-e git+https://github.com/octocat/editable.git@v1.0#egg=editable
+git+file:///srv/git/local.git#egg=local
+git+ssh://git@github.com/octocat/private.git@main#egg=private
+package @ git+https://gitlab.com/group/package.git
+";
+        let urls: Vec<String> = requirements(manifest).iter().map(|url| normalize(url)).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://github.com/octocat/editable.git",
+                "file:///srv/git/local.git",
+                "ssh://git@github.com/octocat/private.git",
+                "https://gitlab.com/group/package.git",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gemfile() {
+        let manifest = r#"source "https://rubygems.org"
+gem "rails", "~> 7.0"
+gem "engine", git: "https://github.com/octocat/engine.git", branch: "main"
+gem 'legacy', :git => 'https://example.com/legacy.git'
+gem "hub", github: "octocat/hub"
+gem "old", :github => "octocat/old"
+# gem "disabled", git: "https://example.com/disabled.git"
+"#;
+        assert_eq!(
+            gemfile(manifest),
+            vec![
+                "https://github.com/octocat/engine.git",
+                "https://example.com/legacy.git",
+                "https://github.com/octocat/hub",
+                "https://github.com/octocat/old",
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::bundle::BundleDestination;
+use crate::gitea::GiteaDestination;
+use crate::config::{Config, DestinationConfig, DestinationKind, Visibility};
+use crate::metadata::{MetadataArchive, Profile};
+use glob::Pattern;
+use std::path::Path;
+
+/// Somewhere an archived mirror can be copied to.
+pub trait Destination {
+    /// Makes sure the repository described by `request` exists at the
+    /// destination and pushes the mirror at `repo_path` to it.
+    fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Pushes the wiki mirror at `wiki_path` to the wiki of the repository
+    /// described by `request`, which has already been pushed. Destinations
+    /// without wikis ignore it.
+    fn push_wiki(&self, _wiki_path: &Path, _request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Recreates the issues and pull requests in `archive` in the issue
+    /// tracker of the repository described by `request`. Destinations
+    /// without an issue tracker ignore them.
+    fn push_issues(&self, _archive: &MetadataArchive, _request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Recreates the releases in `archive` with their assets. Destinations
+    /// without releases ignore them.
+    fn push_releases(&self, _archive: &MetadataArchive, _request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Applies settings that have to come after everything else was pushed,
+    /// such as making the repository read-only.
+    fn finish(&self, _request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Whether mirrors whose refs have not changed since their last
+    /// successful push are not pushed again, for destinations where every
+    /// push uploads the whole repository. Metadata is still pushed, and such
+    /// destinations skip the files they already have.
+    fn incremental(&self) -> bool {
+        false
+    }
+}
+
+/// Name and settings of a mirror at a destination.
+pub struct PushRequest<'a> {
+    pub owner: &'a str,
+    pub repo: &'a str,
+    /// Description of the original repository, for repositories that have
+    /// to be created.
+    pub description: Option<&'a str>,
+    pub visibility: Visibility,
+    /// Archived profile of the owner, used when the owner has to be created.
+    pub profile: Option<&'a Profile>,
+    pub avatar: Option<&'a Path>,
+    /// Push the LFS objects stored in the mirror along with it.
+    pub lfs: bool,
+    /// The repository is archived on its forge.
+    pub archived: bool,
+    pub topics: &'a [String],
+    /// Public clone URL of the original repository, for destinations that
+    /// can mirror it themselves.
+    pub mirror_of: Option<&'a str>,
+}
+
+/// A configured destination together with the filters deciding which
+/// repositories are sent to it.
+pub struct Target {
+    pub name: String,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    /// Recreate archived issues at the destination.
+    issues: bool,
+    /// Recreate archived releases at the destination.
+    releases: bool,
+    destination: Box<dyn Destination>,
+}
+
+impl Target {
+    pub fn from_config(config: &DestinationConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let destination: Box<dyn Destination> = match &config.kind {
+            DestinationKind::Gitea(gitea) => Box::new(GiteaDestination::new(gitea)),
+            DestinationKind::Gitlab(gitlab) => Box::new(gitlab.clone()),
+            DestinationKind::Bundle(bundle) => Box::new(BundleDestination::new(bundle)),
+            DestinationKind::S3(s3) => Box::new(s3.clone()),
+            DestinationKind::Git(git) => Box::new(git.clone()),
+        };
+        Ok(Target {
+            name: config.name.clone(),
+            include: patterns(&config.include)?,
+            exclude: patterns(&config.exclude)?,
+            issues: config.issues,
+            releases: config.releases,
+            destination,
+        })
+    }
+
+    /// Returns whether `owner/repo` passes this destination's filters.
+    pub fn accepts(&self, owner: &str, repo: &str) -> bool {
+        let full_name = format!("{}/{}", owner, repo);
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&full_name)))
+            && !self.exclude.iter().any(|p| p.matches(&full_name))
+    }
+
+    pub fn incremental(&self) -> bool {
+        self.destination.incremental()
+    }
+
+    pub fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        self.destination.push(repo_path, request)
+    }
+
+    pub fn push_wiki(&self, wiki_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        self.destination.push_wiki(wiki_path, request)
+    }
+
+    pub fn finish(&self, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        self.destination.finish(request)
+    }
+
+    /// Recreates archived issues if the destination is configured to.
+    pub fn push_issues(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.issues {
+            return Ok(());
+        }
+        self.destination.push_issues(archive, request)
+    }
+
+    /// Recreates archived releases if the destination is configured to.
+    pub fn push_releases(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.releases {
+            return Ok(());
+        }
+        self.destination.push_releases(archive, request)
+    }
+}
+
+/// Builds every destination configured in `config`.
+pub fn targets(config: &Config) -> Result<Vec<Target>, Box<dyn std::error::Error>> {
+    config.all_destinations().iter().map(Target::from_config).collect()
+}
@@ -0,0 +1,157 @@
+use crate::config::GitRemoteConfig;
+use crate::destination::{Destination, PushRequest};
+use crate::lfs;
+use duct::cmd;
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+
+/// Where a remote URL points, as far as creating repositories is concerned.
+pub enum Location {
+    Local(PathBuf),
+    /// A path on a host reached with `ssh <destination>`.
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+        /// Relative to the home directory, where ssh runs commands, unless
+        /// absolute. A leading `~/` is removed since the path is quoted.
+        path: String,
+    },
+    /// A server repositories cannot be created on, such as one served over
+    /// HTTPS.
+    Other,
+}
+
+impl Location {
+    pub fn of(url: &str) -> Self {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Location::Local(PathBuf::from(path));
+        }
+        if url.starts_with('/') || url.starts_with("./") || url.starts_with("../") {
+            return Location::Local(PathBuf::from(url));
+        }
+        if url.starts_with("ssh://") {
+            let Ok(parsed) = Url::parse(url) else {
+                return Location::Other;
+            };
+            let Some(host) = parsed.host_str() else {
+                return Location::Other;
+            };
+            let destination = match parsed.username() {
+                "" => host.to_string(),
+                user => format!("{}@{}", user, host),
+            };
+            // `ssh://host/~/path` is relative to the home directory
+            let path = parsed.path();
+            let path = path.strip_prefix('/').filter(|path| path.starts_with("~/")).unwrap_or(path);
+            return Location::Ssh {
+                destination,
+                port: parsed.port(),
+                path: home_relative(path),
+            };
+        }
+        // `[user@]host:path`, the form scp uses
+        match url.split_once(':') {
+            Some((destination, path)) if !url.contains("://") && !destination.contains('/') => Location::Ssh {
+                destination: destination.to_string(),
+                port: None,
+                path: home_relative(path),
+            },
+            _ => Location::Other,
+        }
+    }
+}
+
+impl Destination for GitRemoteConfig {
+    fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let url = self.url_for(request.owner, request.repo);
+        self.push_to(repo_path, &url)?;
+        if self.lfs && request.lfs {
+            lfs::push(repo_path, &url)?;
+        }
+        Ok(())
+    }
+
+    fn push_wiki(&self, wiki_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let url = self.url_for(request.owner, &format!("{}.wiki", request.repo));
+        self.push_to(wiki_path, &url)
+    }
+}
+
+impl GitRemoteConfig {
+    pub fn url_for(&self, owner: &str, repo: &str) -> String {
+        self.url.replace("{owner}", owner).replace("{repo}", repo)
+    }
+
+    fn push_to(&self, repo_path: &Path, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.create {
+            create_bare(url)?;
+        }
+        let mut args = vec!["--git-dir".to_string(), repo_path.display().to_string(), "push".to_string()];
+        if self.refspecs.is_empty() {
+            args.push("--mirror".to_string());
+            args.push(url.to_string());
+        } else {
+            args.push(url.to_string());
+            args.extend(self.refspecs.iter().cloned());
+        }
+        cmd("git", args).run()?;
+        Ok(())
+    }
+}
+
+/// Creates a bare repository at `url` unless one exists.
+fn create_bare(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match Location::of(url) {
+        Location::Local(path) => {
+            // git init creates missing parent directories
+            if !path.exists() {
+                cmd!("git", "init", "--bare", "--quiet", &path).run()?;
+            }
+        }
+        Location::Ssh { destination, port, path } => {
+            let path = shell_quote(&path);
+            let mut args = Vec::new();
+            if let Some(port) = port {
+                args.extend(["-p".to_string(), port.to_string()]);
+            }
+            args.push(destination);
+            args.push(format!("test -d {0} || git init --bare --quiet {0}", path));
+            cmd("ssh", args).run()?;
+        }
+        Location::Other => return Err(format!("Cannot create a repository at '{}'", url).into()),
+    }
+    Ok(())
+}
+
+/// `path` without a leading `~/`, which the shell would no longer expand
+/// once quoted.
+fn home_relative(path: &str) -> String {
+    path.strip_prefix("~/").unwrap_or(path).to_string()
+}
+
+/// Quotes `value` for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh_path(url: &str) -> Option<String> {
+        match Location::of(url) {
+            Location::Ssh { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_location_of_ssh_paths() {
+        assert_eq!(ssh_path("git@example.com:~/repos/app.git"), Some("repos/app.git".to_string()));
+        assert_eq!(ssh_path("git@example.com:repos/app.git"), Some("repos/app.git".to_string()));
+        assert_eq!(ssh_path("example.com:/srv/git/app.git"), Some("/srv/git/app.git".to_string()));
+        assert_eq!(ssh_path("ssh://git@example.com:2222/~/repos/app.git"), Some("repos/app.git".to_string()));
+        assert_eq!(ssh_path("ssh://git@example.com/srv/git/app.git"), Some("/srv/git/app.git".to_string()));
+        assert_eq!(ssh_path("https://example.com/app.git"), None);
+    }
+}
@@ -1,14 +1,35 @@
-use reqwest::blocking::Client;
+use crate::config::{parse_interval, GiteaConfig, Visibility};
+use crate::destination::{Destination, PushRequest};
+use crate::lfs;
+use crate::metadata::{attributed, origin_of, IssueRecord, MetadataArchive, Profile};
+use base64::Engine;
+use crate::source::{host_of, strings, GitCredential, RemoteRepo, Source};
+use duct::cmd;
+use reqwest::blocking::{multipart, Client, RequestBuilder};
+use reqwest::{Method, StatusCode, Url};
+use serde_json::{json, Value};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
 
-pub fn create_org(url: &str, token: &str, org_name: &str) -> bool {
+/// Number of items requested per page from list endpoints.
+const PAGE_LIMIT: usize = 50;
+
+/// Creates an organization, described by the archived profile of the
+/// account it mirrors when there is one.
+pub fn create_org(url: &str, token: &str, org_name: &str, visibility: Visibility, profile: Option<&Profile>) -> bool {
     let client = Client::new();
     let new_org = serde_json::json!({
         "username": org_name,
-        "full_name": format!("{} Full Name", org_name),
-        "description": format!("{} is a great organization.", org_name),
-        "website": "",
-        "location": "World",
-        "visibility": "private",
+        "full_name": profile.and_then(|p| p.name.as_deref()).unwrap_or(org_name),
+        "description": profile.and_then(|p| p.description.as_deref()).unwrap_or_default(),
+        "website": profile.and_then(|p| p.blog.as_deref()).unwrap_or_default(),
+        "location": profile.and_then(|p| p.location.as_deref()).unwrap_or_default(),
+        "visibility": match visibility {
+            Visibility::Public => "public",
+            Visibility::Private => "private",
+        },
     });
 
     let res = client
@@ -30,12 +51,12 @@ pub fn check_repo_exists(url: &str, token: &str, org_name: &str, repo_name: &str
     matches!(res, Ok(response) if response.status().is_success())
 }
 
-pub fn create_repo(url: &str, token: &str, org_name: &str, repo_name: &str) -> bool {
+pub fn create_repo(url: &str, token: &str, org_name: &str, repo_name: &str, description: &str, visibility: Visibility) -> bool {
     let client = Client::new();
     let new_repo = serde_json::json!({
         "name": repo_name,
-        "description": format!("{} is a great repository.", repo_name),
-        "private": true,
+        "description": description,
+        "private": visibility == Visibility::Private,
     });
 
     let res = client
@@ -63,11 +84,651 @@ pub fn check_user_or_org_exists(url: &str, token: &str, name: &str) -> bool {
     org_res.is_ok() && org_res.unwrap().status().is_success()
 }
 
-pub fn create_org_if_no_conflict(url: &str, token: &str, org_name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+/// Replaces the avatar of an organization with the image at `path`.
+pub fn set_org_avatar(url: &str, token: &str, org_name: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let image = base64::engine::general_purpose::STANDARD.encode(std::fs::read(path)?);
+    let client = Client::new();
+    let res = client
+        .post(format!("{}/api/v1/orgs/{}/avatar", url, org_name))
+        .bearer_auth(token)
+        .json(&json!({ "image": image }))
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(())
+}
+
+pub fn create_org_if_no_conflict(url: &str, token: &str, org_name: &str, visibility: Visibility, profile: Option<&Profile>) -> Result<bool, Box<dyn std::error::Error>> {
     if check_user_or_org_exists(url, token, org_name) {
         Ok(false) // Organization already exists
     } else {
-        Ok(create_org(url, token, org_name))
+        Ok(create_org(url, token, org_name, visibility, profile))
+    }
+}
+
+
+/// Returns the login of the user the token belongs to.
+pub fn whoami(url: &str, token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = client
+        .get(format!("{}/api/v1/user", url))
+        .bearer_auth(token)
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+
+    let user: serde_json::Value = res.json()?;
+    user["login"]
+        .as_str()
+        .map(|login| login.to_string())
+        .ok_or_else(|| "Unexpected response format from Gitea API".into())
+}
+
+fn authorize(token: &str, request: RequestBuilder) -> RequestBuilder {
+    if token.is_empty() {
+        request
+    } else {
+        request.bearer_auth(token)
+    }
+}
+
+/// Calls a Gitea API endpoint, such as `repos/owner/repo`.
+pub fn api(url: &str, token: &str, endpoint: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(token, client.get(format!("{}/api/v1/{}", url, endpoint))).send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(res.json()?)
+}
+
+/// Calls a Gitea API endpoint returning a list, requesting pages until one
+/// comes back short. Returns `None` when the endpoint does not exist, such as
+/// an organization name that is actually a user.
+pub fn api_paginated(url: &str, token: &str, endpoint: &str) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let separator = if endpoint.contains('?') { '&' } else { '?' };
+    let mut items = Vec::new();
+
+    for page in 1.. {
+        let page_url = format!("{}/api/v1/{}{}limit={}&page={}", url, endpoint, separator, PAGE_LIMIT, page);
+        let res = authorize(token, client.get(&page_url)).send()?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(format!("{} returned {}", res.url(), res.status()).into());
+        }
+
+        let page_items = match res.json()? {
+            Value::Array(page_items) => page_items,
+            _ => return Err("Unexpected response format from Gitea API".into()),
+        };
+        let last_page = page_items.len() < PAGE_LIMIT;
+        items.extend(page_items);
+        if last_page {
+            break;
+        }
+    }
+    Ok(Some(items))
+}
+
+/// Sends `body` to a Gitea API endpoint with `method`, such as a `POST` to
+/// `repos/owner/repo/issues`, and returns the response.
+fn send(config: &GiteaConfig, method: Method, endpoint: &str, body: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(config.token.expose(), client.request(method, format!("{}/api/v1/{}", config.url, endpoint)))
+        .json(body)
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    // Some endpoints, such as the one replacing topics, answer with no content
+    let text = res.text()?;
+    if text.is_empty() {
+        return Ok(Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Which implementation a Gitea-compatible server runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Gitea,
+    /// Forgejo, which also serves an API of its own under `/api/forgejo/v1`.
+    Forgejo,
+}
+
+pub struct Server {
+    pub flavor: Flavor,
+    pub version: String,
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.flavor {
+            Flavor::Gitea => write!(f, "Gitea {}", self.version),
+            Flavor::Forgejo => write!(f, "Forgejo {}", self.version),
+        }
+    }
+}
+
+/// Asks the server at `url` for its version, telling Forgejo apart from
+/// Gitea by the version endpoint only Forgejo has.
+pub fn detect_server(url: &str, token: &str) -> Result<Server, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(token, client.get(format!("{}/api/forgejo/v1/version", url))).send()?;
+    if res.status().is_success() {
+        let version: Value = res.json()?;
+        return Ok(Server {
+            flavor: Flavor::Forgejo,
+            version: version["version"].as_str().unwrap_or_default().to_string(),
+        });
+    }
+
+    let version = api(url, token, "version")?;
+    Ok(Server {
+        flavor: Flavor::Gitea,
+        version: version["version"].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+/// Lists repositories from Gitea and compatible servers such as Forgejo and
+/// Codeberg.
+pub struct GiteaSource {
+    forge: String,
+    config: GiteaConfig,
+}
+
+impl GiteaSource {
+    pub fn new(forge: &str, config: GiteaConfig) -> Self {
+        GiteaSource {
+            forge: forge.to_string(),
+            config,
+        }
+    }
+
+    fn parse_repo(&self, repo: &Value) -> Option<RemoteRepo> {
+        let owner = repo["owner"]["login"]
+            .as_str()
+            .or_else(|| repo["owner"]["username"].as_str())?;
+        let name = repo["name"].as_str()?;
+        Some(RemoteRepo {
+            forge: self.forge.clone(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+            path: self.local_path(owner, name),
+            clone_url: repo["clone_url"]
+                .as_str()
+                .map_or_else(|| self.clone_url(owner, name), |url| url.to_string()),
+            description: repo["description"].as_str().map(|s| s.to_string()),
+            default_branch: repo["default_branch"].as_str().map(|s| s.to_string()),
+            fork: repo["fork"].as_bool().unwrap_or(false),
+            archived: repo["archived"].as_bool().unwrap_or(false),
+            private: repo["private"].as_bool().unwrap_or(false),
+            has_wiki: repo["has_wiki"].as_bool().unwrap_or(false),
+            topics: strings(&repo["topics"]),
+            gist: false,
+        })
+    }
+
+    fn parse_repos(&self, repos: Vec<Value>) -> Vec<RemoteRepo> {
+        repos.iter().filter_map(|repo| self.parse_repo(repo)).collect()
+    }
+}
+
+impl Source for GiteaSource {
+    fn forge(&self) -> &str {
+        &self.forge
+    }
+
+    fn list_repositories(&self, user_or_org: &str) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let (url, token) = (&self.config.url, self.config.token.expose());
+        if let Some(repos) = api_paginated(url, token, &format!("orgs/{}/repos", user_or_org))? {
+            return Ok(self.parse_repos(repos));
+        }
+
+        let repos = api_paginated(url, token, &format!("users/{}/repos", user_or_org))?
+            .ok_or_else(|| format!("No organization or user named '{}' on {}", user_or_org, url))?;
+        Ok(self.parse_repos(repos))
+    }
+
+    fn list_starred(&self) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        if self.config.token.is_empty() {
+            return Err(format!("Listing starred repositories on {} requires a token", self.config.url).into());
+        }
+        let repos = api_paginated(&self.config.url, self.config.token.expose(), "user/starred")?.unwrap_or_default();
+        Ok(self.parse_repos(repos))
+    }
+
+    fn clone_url(&self, user_or_org: &str, repo: &str) -> String {
+        format!("{}/{}/{}.git", self.config.url.trim_end_matches('/'), user_or_org, repo)
+    }
+
+    fn git_credential(&self) -> Option<GitCredential> {
+        // Gitea takes a token as the password of any user name
+        let token = self.config.token.expose();
+        (!token.is_empty()).then(|| GitCredential::basic(&self.config.url, "oauth2", token))
+    }
+
+    fn metadata(&self, user_or_org: &str, repo: &str) -> Result<RemoteRepo, Box<dyn std::error::Error>> {
+        let repo = api(
+            &self.config.url,
+            self.config.token.expose(),
+            &format!("repos/{}/{}", user_or_org, repo),
+        )?;
+        self.parse_repo(&repo)
+            .ok_or_else(|| "Unexpected response format from Gitea API".into())
+    }
+
+    fn local_path(&self, user_or_org: &str, repo: &str) -> String {
+        format!("{}/{}/{}", host_of(&self.config.url), user_or_org, repo)
+    }
+}
+
+/// A Gitea or Forgejo server repositories are pushed to. What the server
+/// runs is detected on first use; the `archive`, `topics` and
+/// `mirror_interval` settings only apply to Forgejo.
+pub struct GiteaDestination {
+    config: GiteaConfig,
+    flavor: OnceCell<Flavor>,
+}
+
+impl GiteaDestination {
+    pub fn new(config: &GiteaConfig) -> Self {
+        GiteaDestination {
+            config: config.clone(),
+            flavor: OnceCell::new(),
+        }
+    }
+
+    fn forgejo(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some(flavor) = self.flavor.get() {
+            return Ok(*flavor == Flavor::Forgejo);
+        }
+        let server = detect_server(&self.config.url, self.config.token.expose())?;
+        let forgejo_settings = self.config.archive || self.config.topics || self.config.mirror_interval.is_some();
+        if server.flavor == Flavor::Gitea && forgejo_settings {
+            eprintln!("{} runs {}; archive, topics and mirror_interval only apply to Forgejo", self.config.url, server);
+        }
+        Ok(*self.flavor.get_or_init(|| server.flavor) == Flavor::Forgejo)
+    }
+
+    /// Whether repositories are mirrored by the server itself, in which case
+    /// nothing is pushed to them.
+    fn mirrors(&self, request: &PushRequest) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.config.mirror_interval.is_some() && request.mirror_of.is_some() && self.forgejo()?)
+    }
+}
+
+/// The owner of `request` at Gitea, which has no nested organizations:
+/// `group/subgroup` becomes `group-subgroup`.
+fn flat_owner(request: &PushRequest) -> String {
+    request.owner.replace('/', "-")
+}
+
+/// `request` for `owner` instead of its own.
+fn with_owner<'a>(request: &PushRequest<'a>, owner: &'a str) -> PushRequest<'a> {
+    PushRequest { owner, ..*request }
+}
+
+impl Destination for GiteaDestination {
+    fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let owner = flat_owner(request);
+        let request = &with_owner(request, &owner);
+        let config = &self.config;
+        if self.mirrors(request)? && sync_pull_mirror(config, request)? {
+            return Ok(());
+        }
+        ensure_repo_exists(config, request)?;
+        if config.archive && self.forgejo()? {
+            // Archived repositories reject pushes until they are unarchived
+            let endpoint = format!("repos/{}/{}", request.owner, request.repo);
+            send(config, Method::PATCH, &endpoint, &json!({ "archived": false }))?;
+        }
+        push_mirror(config, repo_path, request.owner, request.repo)?;
+        if request.lfs {
+            let url = authenticated_url(config, &format!("{}/{}.git", request.owner, request.repo))?;
+            lfs::push(repo_path, url.as_str())?;
+        }
+        Ok(())
+    }
+
+    fn push_wiki(&self, wiki_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let owner = flat_owner(request);
+        let request = &with_owner(request, &owner);
+        let endpoint = format!("repos/{}/{}", request.owner, request.repo);
+        // Pull mirrors fetch the wiki themselves
+        if self.mirrors(request)? && api(&self.config.url, self.config.token.expose(), &endpoint)?["mirror"] == true {
+            return Ok(());
+        }
+        // Gitea creates the wiki repository on the first push once it is enabled
+        send(&self.config, Method::PATCH, &endpoint, &json!({ "has_wiki": true }))?;
+        push_mirror(&self.config, wiki_path, request.owner, &format!("{}.wiki", request.repo))
+    }
+
+    fn push_issues(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let owner = flat_owner(request);
+        push_issues(&self.config, archive, &with_owner(request, &owner))
+    }
+
+    fn push_releases(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let owner = flat_owner(request);
+        push_releases(&self.config, archive, &with_owner(request, &owner))
+    }
+
+    /// Copies the topics of the original repository and makes it read-only
+    /// if it is archived, on Forgejo.
+    fn finish(&self, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        if !(self.config.topics || self.config.archive) || !self.forgejo()? {
+            return Ok(());
+        }
+        let endpoint = format!("repos/{}/{}", flat_owner(request), request.repo);
+        if self.config.topics {
+            let topics: Vec<String> = request
+                .topics
+                .iter()
+                .map(|topic| topic.to_lowercase())
+                .filter(|topic| is_valid_topic(topic))
+                .collect();
+            send(&self.config, Method::PUT, &format!("{}/topics", endpoint), &json!({ "topics": topics }))?;
+        }
+        if self.config.archive && request.archived {
+            send(&self.config, Method::PATCH, &endpoint, &json!({ "archived": true }))?;
+        }
+        Ok(())
     }
 }
 
+/// Whether Gitea accepts `topic`: up to 35 lowercase letters, digits, dashes
+/// and dots, starting with a letter or digit.
+fn is_valid_topic(topic: &str) -> bool {
+    topic.len() <= 35
+        && topic.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && topic.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+}
+
+/// Makes the server mirror the original repository of `request` itself:
+/// creates the repository as a pull mirror syncing every `mirror_interval`,
+/// or asks an existing pull mirror to sync now. Returns `false` for
+/// repositories that exist as regular ones, which keep being pushed to.
+fn sync_pull_mirror(config: &GiteaConfig, request: &PushRequest) -> Result<bool, Box<dyn std::error::Error>> {
+    let (Some(clone_url), Some(interval)) = (request.mirror_of, &config.mirror_interval) else {
+        return Ok(false);
+    };
+    let endpoint = format!("repos/{}/{}", request.owner, request.repo);
+    if check_repo_exists(&config.url, config.token.expose(), request.owner, request.repo) {
+        let repo = api(&config.url, config.token.expose(), &endpoint)?;
+        if repo["mirror"] != true {
+            return Ok(false);
+        }
+        // Archived mirrors no longer sync, and their original does not change
+        if repo["archived"] != true {
+            send(config, Method::POST, &format!("{}/mirror-sync", endpoint), &json!({}))?;
+        }
+        return Ok(true);
+    }
+
+    // Forgejo takes a Go duration, which has no unit for days or weeks
+    let interval = format!("{}s", parse_interval(interval)?.as_secs());
+    ensure_owner_exists(config, request)?;
+    send(config, Method::POST, "repos/migrate", &json!({
+        "clone_addr": clone_url,
+        "repo_owner": request.owner,
+        "repo_name": request.repo,
+        "service": "git",
+        "mirror": true,
+        "mirror_interval": interval,
+        "description": request.description.unwrap_or_default(),
+        "private": request.visibility == Visibility::Private,
+        "wiki": true,
+        "lfs": request.lfs,
+    }))
+    .map_err(|e| format!("Failed to create pull mirror in Forgejo: {}/{}: {}", request.owner, request.repo, e))?;
+    println!("Created new pull mirror in Forgejo: {}/{}", request.owner, request.repo);
+    Ok(true)
+}
+
+fn push_issues(config: &GiteaConfig, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint = format!("repos/{}/{}", request.owner, request.repo);
+    let labels = sync_labels(config, &endpoint, &archive.labels()?)?;
+    let milestones = sync_milestones(config, &endpoint, &archive.milestones()?)?;
+
+    // Issues recreated by earlier runs are recognized by the original URL
+    // in the first line of their body
+    let existing = api_paginated(
+        &config.url,
+        config.token.expose(),
+        &format!("{}/issues?state=all&type=issues", endpoint),
+    )?
+    .unwrap_or_default();
+    let recreated: HashMap<&str, (u64, &str)> = existing
+        .iter()
+        .filter_map(|issue| {
+            let origin = origin_of(issue["body"].as_str()?)?;
+            Some((origin, (issue["number"].as_u64()?, issue["state"].as_str()?)))
+        })
+        .collect();
+
+    for record in archive.issues()? {
+        let issue = &record.issue;
+        let Some(origin) = issue["html_url"].as_str() else {
+            continue;
+        };
+        let state = issue["state"].as_str().unwrap_or("open");
+        let number = match recreated.get(origin) {
+            Some(&(number, current_state)) => {
+                if current_state != state {
+                    send(config, Method::PATCH, &format!("{}/issues/{}", endpoint, number), &json!({ "state": state }))?;
+                }
+                number
+            }
+            None => {
+                let kind = if record.pull_request.is_some() { "pull request" } else { "issue" };
+                let new_issue = json!({
+                    "title": issue["title"],
+                    "body": attributed(kind, issue, "created_at", issue["body"].as_str().unwrap_or_default()),
+                    "labels": names_to_ids(&labels, issue["labels"].as_array(), "name"),
+                    "milestone": issue["milestone"]["title"].as_str().and_then(|title| milestones.get(title)),
+                    "closed": state == "closed",
+                });
+                let created = send(config, Method::POST, &format!("{}/issues", endpoint), &new_issue)?;
+                created["number"]
+                    .as_u64()
+                    .ok_or("Unexpected response format from Gitea API")?
+            }
+        };
+        sync_comments(config, &format!("{}/issues/{}/comments", endpoint, number), &record)?;
+    }
+    Ok(())
+}
+
+/// Creates the archived releases missing from the repository, whose tags
+/// have been pushed with the mirror, and uploads the assets they lack.
+fn push_releases(config: &GiteaConfig, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint = format!("repos/{}/{}/releases", request.owner, request.repo);
+    let existing = api_paginated(&config.url, config.token.expose(), &endpoint)?.unwrap_or_default();
+
+    for record in archive.releases()? {
+        let release = &record.release;
+        // Drafts may point at tags that do not exist yet
+        let Some(tag) = release["tag_name"].as_str().filter(|_| release["draft"] != true) else {
+            continue;
+        };
+        let current = match existing.iter().find(|current| current["tag_name"] == tag) {
+            Some(current) => current.clone(),
+            None => send(config, Method::POST, &endpoint, &json!({
+                "tag_name": tag,
+                "name": release["name"],
+                "body": release["body"],
+                "prerelease": release["prerelease"],
+            }))?,
+        };
+        let id = current["id"].as_u64().ok_or("Unexpected response format from Gitea API")?;
+
+        let uploaded: Vec<&str> = current["assets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|asset| asset["name"].as_str())
+            .collect();
+        for asset in record.assets.iter().filter(|asset| !uploaded.contains(&asset.name.as_str())) {
+            let form = multipart::Form::new().file("attachment", archive.asset_path(asset))?;
+            let res = authorize(config.token.expose(), Client::new().post(format!("{}/api/v1/{}/{}/assets", config.url, endpoint, id)))
+                .query(&[("name", &asset.name)])
+                .multipart(form)
+                .send()?;
+            if !res.status().is_success() {
+                return Err(format!("{} returned {}", res.url(), res.status()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates the archived labels missing from the repository and returns the
+/// ID of every label by name.
+fn sync_labels(config: &GiteaConfig, endpoint: &str, labels: &[Value]) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+    let endpoint = format!("{}/labels", endpoint);
+    let mut ids = ids_by(&api_paginated(&config.url, config.token.expose(), &endpoint)?.unwrap_or_default(), "name");
+    for label in labels {
+        let Some(name) = label["name"].as_str() else {
+            continue;
+        };
+        if !ids.contains_key(name) {
+            let created = send(config, Method::POST, &endpoint, &json!({
+                "name": name,
+                "color": format!("#{}", label["color"].as_str().unwrap_or("ededed")),
+                "description": label["description"].as_str().unwrap_or_default(),
+            }))?;
+            ids.extend(ids_by(&[created], "name"));
+        }
+    }
+    Ok(ids)
+}
+
+/// Creates the archived milestones missing from the repository and returns
+/// the ID of every milestone by title.
+fn sync_milestones(config: &GiteaConfig, endpoint: &str, milestones: &[Value]) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+    let endpoint = format!("{}/milestones", endpoint);
+    let existing = api_paginated(&config.url, config.token.expose(), &format!("{}?state=all", endpoint))?.unwrap_or_default();
+    let mut ids = ids_by(&existing, "title");
+    for milestone in milestones {
+        let Some(title) = milestone["title"].as_str() else {
+            continue;
+        };
+        if !ids.contains_key(title) {
+            let created = send(config, Method::POST, &endpoint, &json!({
+                "title": title,
+                "description": milestone["description"].as_str().unwrap_or_default(),
+                "due_on": milestone["due_on"],
+                "state": milestone["state"].as_str().unwrap_or("open"),
+            }))?;
+            ids.extend(ids_by(&[created], "title"));
+        }
+    }
+    Ok(ids)
+}
+
+/// Adds the comments, reviews and review comments of an archived issue that
+/// are missing from the recreated one, oldest first.
+fn sync_comments(config: &GiteaConfig, endpoint: &str, record: &IssueRecord) -> Result<(), Box<dyn std::error::Error>> {
+    let comments = record.attributed_comments();
+    if comments.is_empty() {
+        return Ok(());
+    }
+
+    // The endpoint listing issue comments ignores `limit` and `page` and
+    // always returns every comment
+    let existing = api(&config.url, config.token.expose(), endpoint)?;
+    let recreated: Vec<&str> = existing
+        .as_array()
+        .ok_or("Unexpected response format from Gitea API")?
+        .iter()
+        .filter_map(|comment| origin_of(comment["body"].as_str()?))
+        .collect();
+    for body in comments {
+        if !origin_of(&body).is_some_and(|origin| recreated.contains(&origin)) {
+            send(config, Method::POST, endpoint, &json!({ "body": body }))?;
+        }
+    }
+    Ok(())
+}
+
+fn ids_by(items: &[Value], key: &str) -> HashMap<String, u64> {
+    items
+        .iter()
+        .filter_map(|item| Some((item[key].as_str()?.to_string(), item["id"].as_u64()?)))
+        .collect()
+}
+
+fn names_to_ids(ids: &HashMap<String, u64>, items: Option<&Vec<Value>>, key: &str) -> Vec<u64> {
+    items
+        .into_iter()
+        .flatten()
+        .filter_map(|item| ids.get(item[key].as_str()?).copied())
+        .collect()
+}
+
+fn ensure_owner_exists(config: &GiteaConfig, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+    let token = config.token.expose();
+    let user_or_org = request.owner;
+
+    // First, ensure the organization exists
+    match create_org_if_no_conflict(&config.url, token, user_or_org, request.visibility, request.profile) {
+        Ok(created) => {
+            if created {
+                println!("Created new organization in Gitea: {}", user_or_org);
+                if let Some(avatar) = request.avatar {
+                    if let Err(e) = set_org_avatar(&config.url, token, user_or_org, avatar) {
+                        eprintln!("Failed to set the avatar of {} in Gitea: {}", user_or_org, e);
+                    }
+                }
+            }
+        },
+        Err(e) => {
+            return Err(format!("Failed to create organization in Gitea: {}: {}", user_or_org, e).into());
+        }
+    }
+    Ok(())
+}
+
+fn ensure_repo_exists(config: &GiteaConfig, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+    let token = config.token.expose();
+    let (user_or_org, repo) = (request.owner, request.repo);
+    ensure_owner_exists(config, request)?;
+
+    // Then, check if the repository exists and create it if it doesn't
+    if !check_repo_exists(&config.url, token, user_or_org, repo) {
+        let description = request.description.unwrap_or_default();
+        if create_repo(&config.url, token, user_or_org, repo, description, request.visibility) {
+            println!("Created new repository in Gitea: {}/{}", user_or_org, repo);
+        } else {
+            return Err(format!("Failed to create repository in Gitea: {}/{}", user_or_org, repo).into());
+        }
+    }
+    Ok(())
+}
+
+fn push_mirror(config: &GiteaConfig, repo_path: &Path, org_name: &str, repo_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let url = authenticated_url(config, &format!("{}/{}.git", org_name, repo_name))?;
+    cmd!("git", "--git-dir", repo_path, "push", "--mirror", url.as_str()).run()?;
+    Ok(())
+}
+
+/// Builds the URL of `path` on the Gitea server with the push credentials
+/// embedded, percent-encoding them as needed.
+fn authenticated_url(config: &GiteaConfig, path: &str) -> Result<Url, Box<dyn std::error::Error>> {
+    let mut url = Url::parse(&format!("{}/", config.url.trim_end_matches('/')))?.join(path)?;
+    url.set_username(&config.username)
+        .and_then(|_| url.set_password(Some(config.password.expose())))
+        .map_err(|_| format!("Cannot add credentials to Gitea URL '{}'", config.url))?;
+    Ok(url)
+}
@@ -1,42 +1,366 @@
-use duct::cmd;
-use std::process::Command;
+use crate::config::GithubConfig;
+use crate::metadata::{IssueRecord, MetadataArchive, Profile, ProfileArchive, ReleaseRecord, FORMAT_VERSION};
+use crate::source::{strings, RemoteRepo, Source};
+use duct::{cmd, Expression};
 use serde_json::Value;
 
-pub fn get_starred_repositories() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let output = cmd!(
-        "gh",
-        "api",
-        "/user/starred?per_page=100",
-        "-q",
-        ".[].full_name"
-    )
-    .read()?;
-    let repos = output
-        .lines()
-        .map(|line| line.to_string())
-        .collect::<Vec<String>>();
-    Ok(repos)
-}
-
-pub fn get_repositories(user_or_org: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let output = Command::new("gh")
-        .args(&["api", &format!("users/{}/repos", user_or_org), "--paginate"])
-        .output()?;
+/// Runs `gh` with the configured token, falling back to the CLI's own login
+/// when no token is configured.
+fn gh(config: &GithubConfig, args: &[&str]) -> Expression {
+    let expression = cmd("gh", args);
+    if config.token.is_empty() {
+        expression
+    } else {
+        expression.env("GH_TOKEN", config.token.expose())
+    }
+}
+
+/// Calls a GitHub API endpoint through the GitHub CLI.
+pub fn api(config: &GithubConfig, endpoint: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let output = gh(config, &["api", endpoint])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
 
     if !output.status.success() {
         return Err(format!("GitHub CLI command failed: {}", String::from_utf8_lossy(&output.stderr)).into());
     }
 
-    let stdout = String::from_utf8(output.stdout)?;
-    let repos: Value = serde_json::from_str(&stdout)?;
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Fetches the raw content of a file, such as `repos/o/r/readme`.
+fn api_raw(config: &GithubConfig, endpoint: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = gh(config, &["api", endpoint, "-H", "Accept: application/vnd.github.raw"])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
 
-    if let Value::Array(repos) = repos {
-        let repos = repos
-            .into_iter()
-            .filter_map(|repo| repo["name"].as_str().map(|s| s.to_string()))
-            .collect();
-        Ok(repos)
-    } else {
-        Err("Unexpected response format from GitHub API".into())
+    if !output.status.success() {
+        return Err(format!("GitHub CLI command failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+const PINNED_QUERY: &str = "query($login: String!) {
+  repositoryOwner(login: $login) {
+    ... on ProfileOwner {
+      pinnedItems(first: 6, types: REPOSITORY) { nodes { ... on Repository { nameWithOwner } } }
+    }
+  }
+}";
+
+/// The repositories pinned to a profile, which only the GraphQL API reports.
+fn pinned_repositories(config: &GithubConfig, login: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let query = format!("query={}", PINNED_QUERY);
+    let login = format!("login={}", login);
+    let output = gh(config, &["api", "graphql", "-f", &query, "-F", &login])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+
+    if !output.status.success() {
+        return Err(format!("GitHub CLI command failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    let response: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(response["data"]["repositoryOwner"]["pinnedItems"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|node| node["nameWithOwner"].as_str().map(|s| s.to_string()))
+        .collect())
+}
+
+/// A non-empty string field of an API response.
+fn text(value: &Value) -> Option<String> {
+    value.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string())
+}
+
+/// Calls a GitHub API endpoint returning a list, following every page.
+pub fn api_paginated(config: &GithubConfig, endpoint: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let output = gh(config, &["api", endpoint, "--paginate"])
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+
+    if !output.status.success() {
+        return Err(format!("GitHub CLI command failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+
+    // `--paginate` prints one JSON array per page
+    let mut items = Vec::new();
+    for page in serde_json::Deserializer::from_slice(&output.stdout).into_iter::<Value>() {
+        match page? {
+            Value::Array(page) => items.extend(page),
+            _ => return Err("Unexpected response format from GitHub API".into()),
+        }
+    }
+    Ok(items)
+}
+
+/// Downloads the release asset with the ID `id` of the repository at
+/// `endpoint` to `path`.
+fn download_asset(config: &GithubConfig, endpoint: &str, id: u64, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let asset = format!("{}/releases/assets/{}", endpoint, id);
+    let output = gh(config, &["api", &asset, "-H", "Accept: application/octet-stream"])
+        .stdout_path(path)
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+
+    if !output.status.success() {
+        return Err(format!("GitHub CLI command failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    Ok(())
+}
+
+/// Returns the login of the account the configured credentials belong to.
+pub fn whoami(config: &GithubConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let login = gh(config, &["api", "user", "-q", ".login"])
+        .stderr_capture()
+        .read()?;
+    Ok(login.trim().to_string())
+}
+
+pub struct GithubSource {
+    forge: String,
+    config: GithubConfig,
+}
+
+impl GithubSource {
+    pub fn new(forge: &str, config: GithubConfig) -> Self {
+        GithubSource {
+            forge: forge.to_string(),
+            config,
+        }
+    }
+
+    fn parse_repo(&self, repo: &Value) -> Option<RemoteRepo> {
+        let owner = repo["owner"]["login"].as_str()?;
+        let name = repo["name"].as_str()?;
+        Some(RemoteRepo {
+            forge: self.forge.clone(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+            path: self.local_path(owner, name),
+            clone_url: repo["clone_url"]
+                .as_str()
+                .map_or_else(|| self.clone_url(owner, name), |url| url.to_string()),
+            description: repo["description"].as_str().map(|s| s.to_string()),
+            default_branch: repo["default_branch"].as_str().map(|s| s.to_string()),
+            fork: repo["fork"].as_bool().unwrap_or(false),
+            archived: repo["archived"].as_bool().unwrap_or(false),
+            private: repo["private"].as_bool().unwrap_or(false),
+            has_wiki: repo["has_wiki"].as_bool().unwrap_or(false),
+            topics: strings(&repo["topics"]),
+            gist: false,
+        })
+    }
+
+    fn parse_gist(&self, owner: &str, gist: &Value) -> Option<RemoteRepo> {
+        let id = gist["id"].as_str()?;
+        Some(RemoteRepo {
+            forge: self.forge.clone(),
+            owner: owner.to_string(),
+            name: id.to_string(),
+            path: format!("{}/gists/{}", owner, id),
+            clone_url: gist["git_pull_url"]
+                .as_str()
+                .map_or_else(|| format!("https://gist.github.com/{}.git", id), |url| url.to_string()),
+            description: gist["description"].as_str().map(|s| s.to_string()),
+            private: !gist["public"].as_bool().unwrap_or(true),
+            gist: true,
+            ..RemoteRepo::default()
+        })
+    }
+}
+
+impl Source for GithubSource {
+    fn forge(&self) -> &str {
+        &self.forge
+    }
+
+    fn list_repositories(&self, user_or_org: &str) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let repos = api_paginated(&self.config, &format!("users/{}/repos?per_page=100", user_or_org))?;
+        Ok(repos.iter().filter_map(|repo| self.parse_repo(repo)).collect())
+    }
+
+    fn list_starred(&self) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let repos = api_paginated(&self.config, "user/starred?per_page=100")?;
+        Ok(repos.iter().filter_map(|repo| self.parse_repo(repo)).collect())
+    }
+
+    fn archive_profile(&self, owner: &str, archive: &ProfileArchive) -> Result<(), Box<dyn std::error::Error>> {
+        let account = api(&self.config, &format!("users/{}", owner))?;
+        let login = account["login"].as_str().unwrap_or(owner);
+        let mut profile = Profile {
+            login: login.to_string(),
+            organization: account["type"] == "Organization",
+            name: text(&account["name"]),
+            description: text(&account["bio"]),
+            blog: text(&account["blog"]),
+            location: text(&account["location"]),
+            company: text(&account["company"]),
+            email: text(&account["email"]),
+            created_at: text(&account["created_at"]),
+            ..Profile::default()
+        };
+
+        // Organizations show the README of `.github`, users the one of the
+        // repository named after them
+        let readme = if profile.organization {
+            let org = api(&self.config, &format!("orgs/{}", login))?;
+            profile.description = text(&org["description"]);
+            profile.members = api_paginated(&self.config, &format!("orgs/{}/members?per_page=100", login))?
+                .iter()
+                .filter_map(|member| text(&member["login"]))
+                .collect();
+            (format!("{}/.github", login), "contents/profile/README.md")
+        } else {
+            (format!("{}/{}", login, login), "readme")
+        };
+        if let Ok(content) = api_raw(&self.config, &format!("repos/{}/{}", readme.0, readme.1)) {
+            profile.readme_repo = Some(readme.0);
+            profile.readme = Some(content);
+        }
+
+        match pinned_repositories(&self.config, login) {
+            Ok(pinned) => profile.pinned = pinned,
+            Err(e) => eprintln!("Could not list the pinned repositories of {}: {}", login, e),
+        }
+        if let Some(url) = account["avatar_url"].as_str() {
+            let image = reqwest::blocking::get(url)?.error_for_status()?.bytes()?;
+            profile.avatar_sha256 = Some(archive.save_avatar(&image)?);
+        }
+
+        archive.save(profile)
+    }
+
+    fn list_forks(&self, owner: &str, repo: &str, limit: usize) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        // Popular projects have thousands of forks, so only the pages needed
+        // are requested
+        let mut forks = Vec::new();
+        for page in 1.. {
+            let endpoint = format!("repos/{}/{}/forks?sort=stargazers&per_page=100&page={}", owner, repo, page);
+            let Value::Array(items) = api(&self.config, &endpoint)? else {
+                return Err("Unexpected response format from GitHub API".into());
+            };
+            let last_page = items.len() < 100;
+            forks.extend(items.iter().filter_map(|fork| self.parse_repo(fork)));
+            if last_page || forks.len() >= limit {
+                break;
+            }
+        }
+        forks.truncate(limit);
+        Ok(forks)
+    }
+
+    fn list_gists(&self, user: &str) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let gists = api_paginated(&self.config, &format!("users/{}/gists?per_page=100", user))?;
+        Ok(gists.iter().filter_map(|gist| self.parse_gist(user, gist)).collect())
+    }
+
+    fn clone_url(&self, user_or_org: &str, repo: &str) -> String {
+        format!("https://github.com/{}/{}.git", user_or_org, repo)
+    }
+
+    fn metadata(&self, user_or_org: &str, repo: &str) -> Result<RemoteRepo, Box<dyn std::error::Error>> {
+        let repo = api(&self.config, &format!("repos/{}/{}", user_or_org, repo))?;
+        self.parse_repo(&repo)
+            .ok_or_else(|| "Unexpected response format from GitHub API".into())
+    }
+
+    fn archive_issues(&self, repo: &RemoteRepo, archive: &MetadataArchive) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = format!("repos/{}/{}", repo.owner, repo.name);
+        let mut state = archive.state()?;
+
+        archive.save_labels(&api_paginated(&self.config, &format!("{}/labels?per_page=100", endpoint))?)?;
+        archive.save_milestones(&api_paginated(
+            &self.config,
+            &format!("{}/milestones?state=all&per_page=100", endpoint),
+        )?)?;
+
+        // The issues endpoint includes pull requests
+        let mut issues_endpoint = format!("{}/issues?state=all&sort=updated&direction=asc&per_page=100", endpoint);
+        if let Some(since) = &state.issues_since {
+            issues_endpoint.push_str(&format!("&since={}", since));
+        }
+        let issues = api_paginated(&self.config, &issues_endpoint)?;
+        if !issues.is_empty() {
+            println!("Archiving {} updated issues of {}", issues.len(), repo.full_name());
+        }
+
+        for issue in issues {
+            let Some(number) = issue["number"].as_u64() else {
+                continue;
+            };
+            let mut record = IssueRecord {
+                version: FORMAT_VERSION,
+                comments: api_paginated(&self.config, &format!("{}/issues/{}/comments?per_page=100", endpoint, number))?,
+                ..IssueRecord::default()
+            };
+            if issue["pull_request"].is_object() {
+                let pull = format!("{}/pulls/{}", endpoint, number);
+                record.pull_request = Some(api(&self.config, &pull)?);
+                record.reviews = api_paginated(&self.config, &format!("{}/reviews?per_page=100", pull))?;
+                record.review_comments = api_paginated(&self.config, &format!("{}/comments?per_page=100", pull))?;
+            }
+
+            let updated_at = issue["updated_at"].as_str().map(|s| s.to_string());
+            record.issue = issue;
+            archive.save_issue(number, &record)?;
+            if updated_at > state.issues_since {
+                state.issues_since = updated_at;
+            }
+        }
+
+        state.version = FORMAT_VERSION;
+        archive.save_state(&state)
+    }
+
+    fn archive_releases(&self, repo: &RemoteRepo, archive: &MetadataArchive) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = format!("repos/{}/{}", repo.owner, repo.name);
+        // Releases deleted on GitHub stay in the archive
+        let mut records = archive.releases()?;
+
+        for release in api_paginated(&self.config, &format!("{}/releases?per_page=100", endpoint))? {
+            let Some(tag) = release["tag_name"].as_str() else {
+                continue;
+            };
+            let previous = records.iter().position(|record| record.release["id"] == release["id"]);
+
+            let mut assets = Vec::new();
+            for asset in release["assets"].as_array().into_iter().flatten() {
+                let (Some(id), Some(name)) = (asset["id"].as_u64(), asset["name"].as_str()) else {
+                    continue;
+                };
+                let size = asset["size"].as_u64().unwrap_or_default();
+                let stored = previous
+                    .and_then(|i| records[i].assets.iter().find(|stored| stored.name == name && stored.size == size))
+                    .filter(|stored| archive.asset_path(stored).exists());
+                match stored {
+                    Some(stored) => assets.push(stored.clone()),
+                    None => {
+                        println!("Downloading {} of {} {}", name, repo.full_name(), tag);
+                        assets.push(archive.store_asset(tag, name, |path| download_asset(&self.config, &endpoint, id, path))?);
+                    }
+                }
+            }
+
+            let record = ReleaseRecord { release, assets };
+            match previous {
+                Some(i) => records[i] = record,
+                None => records.push(record),
+            }
+            // Saved after every release so finished downloads are not repeated
+            // after an interruption
+            archive.save_releases(&records)?;
+        }
+
+        archive.save_releases(&records)
     }
 }
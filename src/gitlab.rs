@@ -0,0 +1,583 @@
+use crate::config::{GitlabConfig, Visibility};
+use crate::destination::{Destination, PushRequest};
+use crate::lfs;
+use crate::metadata::{attributed, origin_of, MetadataArchive};
+use crate::source::{host_of, strings, GitCredential, RemoteRepo, Source};
+use duct::cmd;
+use reqwest::blocking::{multipart, Client, RequestBuilder};
+use reqwest::{Method, StatusCode, Url};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Encodes a namespace or project path for use as an `:id` in the GitLab API.
+pub fn encode_path(path: &str) -> String {
+    path.replace('/', "%2F")
+}
+
+fn authorize(config: &GitlabConfig, request: RequestBuilder) -> RequestBuilder {
+    if config.token.is_empty() {
+        request
+    } else {
+        request.header("PRIVATE-TOKEN", config.token.expose())
+    }
+}
+
+/// Calls a GitLab API endpoint, such as `projects/group%2Fproject`.
+pub fn api(config: &GitlabConfig, endpoint: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(config, client.get(format!("{}/api/v4/{}", config.url, endpoint))).send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(res.json()?)
+}
+
+/// Returns the username of the user the token belongs to.
+pub fn whoami(config: &GitlabConfig) -> Result<String, Box<dyn std::error::Error>> {
+    let user = api(config, "user")?;
+    user["username"]
+        .as_str()
+        .map(|username| username.to_string())
+        .ok_or_else(|| "Unexpected response format from GitLab API".into())
+}
+
+/// Calls a GitLab API endpoint, returning `None` when what it names does not
+/// exist.
+fn find(config: &GitlabConfig, endpoint: &str) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(config, client.get(format!("{}/api/v4/{}", config.url, endpoint))).send()?;
+
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(Some(res.json()?))
+}
+
+/// Sends `body` to a GitLab API endpoint with `method`, such as a `POST` to
+/// `projects/:id/issues`, and returns the response.
+fn send(config: &GitlabConfig, method: Method, endpoint: &str, body: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(config, client.request(method, format!("{}/api/v4/{}", config.url, endpoint)))
+        .json(body)
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(res.json()?)
+}
+
+/// Sends a multipart form to a GitLab API endpoint, as file uploads require.
+fn send_form(config: &GitlabConfig, method: Method, endpoint: &str, form: multipart::Form) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let res = authorize(config, client.request(method, format!("{}/api/v4/{}", config.url, endpoint)))
+        .multipart(form)
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    Ok(res.json()?)
+}
+
+/// Calls a GitLab API endpoint returning a list, following the
+/// `x-next-page` header through every page. Returns `None` when the endpoint
+/// does not exist, such as a group path that is actually a user.
+pub fn api_paginated(config: &GitlabConfig, endpoint: &str) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let separator = if endpoint.contains('?') { '&' } else { '?' };
+    let mut items = Vec::new();
+    let mut page = "1".to_string();
+
+    loop {
+        let url = format!("{}/api/v4/{}{}per_page=100&page={}", config.url, endpoint, separator, page);
+        let res = authorize(config, client.get(&url)).send()?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(format!("{} returned {}", res.url(), res.status()).into());
+        }
+
+        let next_page = res
+            .headers()
+            .get("x-next-page")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        match res.json()? {
+            Value::Array(page) => items.extend(page),
+            _ => return Err("Unexpected response format from GitLab API".into()),
+        }
+
+        if next_page.is_empty() {
+            return Ok(Some(items));
+        }
+        page = next_page;
+    }
+}
+
+pub struct GitlabSource {
+    forge: String,
+    config: GitlabConfig,
+}
+
+impl GitlabSource {
+    pub fn new(forge: &str, config: GitlabConfig) -> Self {
+        GitlabSource {
+            forge: forge.to_string(),
+            config,
+        }
+    }
+
+    fn parse_project(&self, project: &Value) -> Option<RemoteRepo> {
+        let owner = project["namespace"]["full_path"].as_str()?;
+        let name = project["path"].as_str()?;
+        Some(RemoteRepo {
+            forge: self.forge.clone(),
+            owner: owner.to_string(),
+            name: name.to_string(),
+            path: self.local_path(owner, name),
+            clone_url: project["http_url_to_repo"]
+                .as_str()
+                .map_or_else(|| self.clone_url(owner, name), |url| url.to_string()),
+            description: project["description"].as_str().map(|s| s.to_string()),
+            default_branch: project["default_branch"].as_str().map(|s| s.to_string()),
+            fork: project["forked_from_project"].is_object(),
+            archived: project["archived"].as_bool().unwrap_or(false),
+            private: project["visibility"].as_str() != Some("public"),
+            has_wiki: project["wiki_enabled"].as_bool().unwrap_or(false),
+            // GitLab before 14.0 only has the deprecated `tag_list`
+            topics: strings(project.get("topics").unwrap_or(&project["tag_list"])),
+            gist: false,
+        })
+    }
+
+    fn parse_projects(&self, projects: Vec<Value>) -> Vec<RemoteRepo> {
+        projects
+            .iter()
+            .filter_map(|project| self.parse_project(project))
+            .collect()
+    }
+}
+
+impl Source for GitlabSource {
+    fn forge(&self) -> &str {
+        &self.forge
+    }
+
+    /// Lists the projects of a group including its subgroups, or of a user
+    /// when no group has that path.
+    fn list_repositories(&self, owner: &str) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let group_projects = api_paginated(
+            &self.config,
+            &format!("groups/{}/projects?include_subgroups=true", encode_path(owner)),
+        )?;
+        if let Some(projects) = group_projects {
+            return Ok(self.parse_projects(projects));
+        }
+
+        let user_projects = api_paginated(&self.config, &format!("users/{}/projects", owner))?
+            .ok_or_else(|| format!("No GitLab group or user named '{}' on {}", owner, self.config.url))?;
+        Ok(self.parse_projects(user_projects))
+    }
+
+    fn list_starred(&self) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        if self.config.token.is_empty() {
+            return Err(format!("Listing starred projects on {} requires a token", self.config.url).into());
+        }
+        let projects = api_paginated(&self.config, "projects?starred=true")?.unwrap_or_default();
+        Ok(self.parse_projects(projects))
+    }
+
+    fn clone_url(&self, owner: &str, repo: &str) -> String {
+        format!("{}/{}/{}.git", self.config.url, owner, repo)
+    }
+
+    fn git_credential(&self) -> Option<GitCredential> {
+        let token = self.config.token.expose();
+        (!token.is_empty()).then(|| GitCredential::basic(&self.config.url, "oauth2", token))
+    }
+
+    fn metadata(&self, owner: &str, repo: &str) -> Result<RemoteRepo, Box<dyn std::error::Error>> {
+        let project = api(&self.config, &format!("projects/{}", encode_path(&format!("{}/{}", owner, repo))))?;
+        self.parse_project(&project)
+            .ok_or_else(|| "Unexpected response format from GitLab API".into())
+    }
+
+    fn local_path(&self, owner: &str, repo: &str) -> String {
+        format!("{}/{}/{}", host_of(&self.config.url), owner, repo)
+    }
+}
+
+/// Pushes mirrors to a GitLab instance. Owners become groups, with nested
+/// owners such as `group/subgroup` becoming subgroups, unless a user or group
+/// with that path exists already.
+impl Destination for GitlabConfig {
+    fn push(&self, repo_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let namespace_id = ensure_namespace(self, request)?;
+        ensure_project(self, namespace_id, request)?;
+        let url = authenticated_url(self, &format!("{}/{}.git", request.owner, request.repo))?;
+        cmd!("git", "--git-dir", repo_path, "push", "--mirror", url.as_str()).run()?;
+        if request.lfs {
+            lfs::push(repo_path, url.as_str())?;
+        }
+        Ok(())
+    }
+
+    fn push_wiki(&self, wiki_path: &Path, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        // GitLab creates the wiki repository on the first push once it is enabled
+        send(self, Method::PUT, &project_endpoint(request), &json!({ "wiki_enabled": true }))?;
+        let url = authenticated_url(self, &format!("{}/{}.wiki.git", request.owner, request.repo))?;
+        cmd!("git", "--git-dir", wiki_path, "push", "--mirror", url.as_str()).run()?;
+        Ok(())
+    }
+
+    fn push_issues(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = project_endpoint(request);
+        sync_labels(self, &endpoint, &archive.labels()?)?;
+        let milestones = sync_milestones(self, &endpoint, &archive.milestones()?)?;
+
+        // Issues recreated by earlier runs are recognized by the original URL
+        // in the first line of their description
+        let existing = api_paginated(self, &format!("{}/issues", endpoint))?.unwrap_or_default();
+        let recreated: HashMap<&str, (u64, &str)> = existing
+            .iter()
+            .filter_map(|issue| {
+                let origin = origin_of(issue["description"].as_str()?)?;
+                Some((origin, (issue["iid"].as_u64()?, issue["state"].as_str()?)))
+            })
+            .collect();
+
+        for record in archive.issues()? {
+            let issue = &record.issue;
+            let Some(origin) = issue["html_url"].as_str() else {
+                continue;
+            };
+            let closed = issue["state"] == "closed";
+            let (iid, was_closed) = match recreated.get(origin) {
+                Some(&(iid, state)) => (iid, state == "closed"),
+                None => {
+                    let kind = if record.pull_request.is_some() { "pull request" } else { "issue" };
+                    let labels: Vec<&str> = issue["labels"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|label| label["name"].as_str())
+                        .collect();
+                    let created = send(self, Method::POST, &format!("{}/issues", endpoint), &json!({
+                        "title": issue["title"],
+                        "description": attributed(kind, issue, "created_at", issue["body"].as_str().unwrap_or_default()),
+                        "labels": labels.join(","),
+                        "milestone_id": issue["milestone"]["title"].as_str().and_then(|title| milestones.get(title)),
+                    }))?;
+                    let iid = created["iid"].as_u64().ok_or("Unexpected response format from GitLab API")?;
+                    (iid, false)
+                }
+            };
+            if closed != was_closed {
+                let state_event = if closed { "close" } else { "reopen" };
+                send(self, Method::PUT, &format!("{}/issues/{}", endpoint, iid), &json!({ "state_event": state_event }))?;
+            }
+            sync_notes(self, &format!("{}/issues/{}/notes", endpoint, iid), &record.attributed_comments())?;
+        }
+        Ok(())
+    }
+
+    /// Creates the archived releases missing from the project, whose tags
+    /// have been pushed with the mirror. GitLab releases link to their
+    /// assets, so the files are uploaded to the project and linked.
+    fn push_releases(&self, archive: &MetadataArchive, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = project_endpoint(request);
+        let existing = api_paginated(self, &format!("{}/releases", endpoint))?.unwrap_or_default();
+
+        for record in archive.releases()? {
+            let release = &record.release;
+            // Drafts may point at tags that do not exist yet
+            let Some(tag) = release["tag_name"].as_str().filter(|_| release["draft"] != true) else {
+                continue;
+            };
+            let current = match existing.iter().find(|current| current["tag_name"] == tag) {
+                Some(current) => current.clone(),
+                None => send(self, Method::POST, &format!("{}/releases", endpoint), &json!({
+                    "tag_name": tag,
+                    "name": release["name"].as_str().unwrap_or(tag),
+                    "description": release["body"].as_str().unwrap_or_default(),
+                }))?,
+            };
+
+            let linked: Vec<&str> = current["assets"]["links"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|link| link["name"].as_str())
+                .collect();
+            for asset in record.assets.iter().filter(|asset| !linked.contains(&asset.name.as_str())) {
+                let form = multipart::Form::new().file("file", archive.asset_path(asset))?;
+                let upload = send_form(self, Method::POST, &format!("{}/uploads", endpoint), form)?;
+                let path = upload["full_path"].as_str().ok_or("Unexpected response format from GitLab API")?;
+                let links = format!("{}/releases/{}/assets/links", endpoint, encode_path(tag));
+                send(self, Method::POST, &links, &json!({
+                    "name": asset.name,
+                    "url": format!("{}{}", self.url, path),
+                }))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn project_endpoint(request: &PushRequest) -> String {
+    format!("projects/{}", encode_path(&format!("{}/{}", request.owner, request.repo)))
+}
+
+fn visibility_name(visibility: Visibility) -> &'static str {
+    match visibility {
+        Visibility::Public => "public",
+        Visibility::Private => "private",
+    }
+}
+
+/// Returns the ID of the namespace of `request.owner`, creating the group and
+/// every parent group missing along its path. The group named by the full
+/// path is described by the archived profile of the owner.
+fn ensure_namespace(config: &GitlabConfig, request: &PushRequest) -> Result<u64, Box<dyn std::error::Error>> {
+    if let Some(namespace) = find(config, &format!("namespaces/{}", encode_path(request.owner)))? {
+        return namespace["id"].as_u64().ok_or_else(|| "Unexpected response format from GitLab API".into());
+    }
+
+    let mut parent_id = None;
+    let mut full_path = String::new();
+    for segment in request.owner.split('/') {
+        if !full_path.is_empty() {
+            full_path.push('/');
+        }
+        full_path.push_str(segment);
+        if let Some(group) = find(config, &format!("groups/{}", encode_path(&full_path)))? {
+            parent_id = group["id"].as_u64();
+            continue;
+        }
+
+        let profile = request.profile.filter(|_| full_path == request.owner);
+        let group = send(config, Method::POST, "groups", &json!({
+            "name": profile.and_then(|p| p.name.as_deref()).unwrap_or(segment),
+            "path": segment,
+            "parent_id": parent_id,
+            "description": profile.and_then(|p| p.description.as_deref()).unwrap_or_default(),
+            "visibility": visibility_name(request.visibility),
+        }))
+        .map_err(|e| format!("Failed to create group in GitLab: {}: {}", full_path, e))?;
+        println!("Created new group in GitLab: {}", full_path);
+        parent_id = group["id"].as_u64();
+
+        if let (Some(avatar), Some(id), true) = (request.avatar, parent_id, profile.is_some()) {
+            let set_avatar = || -> Result<Value, Box<dyn std::error::Error>> {
+                let form = multipart::Form::new().file("avatar", avatar)?;
+                send_form(config, Method::PUT, &format!("groups/{}", id), form)
+            };
+            if let Err(e) = set_avatar() {
+                eprintln!("Failed to set the avatar of {} in GitLab: {}", full_path, e);
+            }
+        }
+    }
+    parent_id.ok_or_else(|| "Unexpected response format from GitLab API".into())
+}
+
+fn ensure_project(config: &GitlabConfig, namespace_id: u64, request: &PushRequest) -> Result<(), Box<dyn std::error::Error>> {
+    if find(config, &project_endpoint(request))?.is_some() {
+        return Ok(());
+    }
+    send(config, Method::POST, "projects", &json!({
+        "name": request.repo,
+        "path": request.repo,
+        "namespace_id": namespace_id,
+        "description": request.description.unwrap_or_default(),
+        "visibility": visibility_name(request.visibility),
+        "lfs_enabled": request.lfs,
+    }))
+    .map_err(|e| format!("Failed to create project in GitLab: {}/{}: {}", request.owner, request.repo, e))?;
+    println!("Created new project in GitLab: {}/{}", request.owner, request.repo);
+    Ok(())
+}
+
+/// Creates the archived labels missing from the project.
+fn sync_labels(config: &GitlabConfig, endpoint: &str, labels: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint = format!("{}/labels", endpoint);
+    let existing = api_paginated(config, &endpoint)?.unwrap_or_default();
+    for label in labels {
+        let Some(name) = label["name"].as_str() else {
+            continue;
+        };
+        if !existing.iter().any(|current| current["name"] == name) {
+            send(config, Method::POST, &endpoint, &json!({
+                "name": name,
+                "color": format!("#{}", label["color"].as_str().unwrap_or("ededed")),
+                "description": label["description"].as_str().unwrap_or_default(),
+            }))?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the archived milestones missing from the project and returns the
+/// ID of every milestone by title.
+fn sync_milestones(config: &GitlabConfig, endpoint: &str, milestones: &[Value]) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+    let endpoint = format!("{}/milestones", endpoint);
+    let ids_by_title = |milestones: &[Value]| -> HashMap<String, u64> {
+        milestones
+            .iter()
+            .filter_map(|milestone| Some((milestone["title"].as_str()?.to_string(), milestone["id"].as_u64()?)))
+            .collect()
+    };
+    let mut ids = ids_by_title(&api_paginated(config, &endpoint)?.unwrap_or_default());
+    for milestone in milestones {
+        let Some(title) = milestone["title"].as_str() else {
+            continue;
+        };
+        if ids.contains_key(title) {
+            continue;
+        }
+        // GitLab takes a date where GitHub has a timestamp
+        let due_date = milestone["due_on"].as_str().and_then(|due_on| due_on.get(..10));
+        let created = send(config, Method::POST, &endpoint, &json!({
+            "title": title,
+            "description": milestone["description"].as_str().unwrap_or_default(),
+            "due_date": due_date,
+        }))?;
+        if milestone["state"] == "closed" {
+            if let Some(id) = created["id"].as_u64() {
+                send(config, Method::PUT, &format!("{}/{}", endpoint, id), &json!({ "state_event": "close" }))?;
+            }
+        }
+        ids.extend(ids_by_title(&[created]));
+    }
+    Ok(ids)
+}
+
+/// Adds the archived comments missing from the notes of a recreated issue.
+fn sync_notes(config: &GitlabConfig, endpoint: &str, comments: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if comments.is_empty() {
+        return Ok(());
+    }
+    let existing = api_paginated(config, &format!("{}?sort=asc", endpoint))?.unwrap_or_default();
+    let recreated: Vec<&str> = existing
+        .iter()
+        .filter_map(|note| origin_of(note["body"].as_str()?))
+        .collect();
+    for body in comments {
+        if !origin_of(body).is_some_and(|origin| recreated.contains(&origin)) {
+            send(config, Method::POST, endpoint, &json!({ "body": body }))?;
+        }
+    }
+    Ok(())
+}
+
+/// Builds the URL of `path` on the GitLab instance with the token embedded,
+/// as GitLab accepts personal access tokens as the password of any user name.
+fn authenticated_url(config: &GitlabConfig, path: &str) -> Result<Url, Box<dyn std::error::Error>> {
+    let mut url = Url::parse(&format!("{}/", config.url))?.join(path)?;
+    url.set_username("oauth2")
+        .and_then(|_| url.set_password(Some(config.token.expose())))
+        .map_err(|_| format!("Cannot add credentials to GitLab URL '{}'", config.url))?;
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers one request per connection with `responses` in order and
+    /// returns the request lines and bodies it received.
+    fn serve(responses: Vec<(u16, &'static str)>) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+                requests.push((request_line.trim().to_string(), String::from_utf8(request_body).unwrap()));
+
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_ensure_namespace_creates_subgroups() {
+        let (url, server) = serve(vec![
+            (404, "{}"),
+            (404, "{}"),
+            (201, r#"{"id": 1}"#),
+            (404, "{}"),
+            (201, r#"{"id": 2}"#),
+        ]);
+        let config = GitlabConfig { url, token: Secret::new("glpat-123") };
+        let request = PushRequest {
+            owner: "team/sub",
+            repo: "app",
+            description: None,
+            visibility: Visibility::Private,
+            profile: None,
+            avatar: None,
+            lfs: false,
+            archived: false,
+            topics: &[],
+            mirror_of: None,
+        };
+
+        assert_eq!(ensure_namespace(&config, &request).unwrap(), 2);
+
+        let requests = server.join().unwrap();
+        let lines: Vec<&str> = requests.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "GET /api/v4/namespaces/team%2Fsub HTTP/1.1",
+                "GET /api/v4/groups/team HTTP/1.1",
+                "POST /api/v4/groups HTTP/1.1",
+                "GET /api/v4/groups/team%2Fsub HTTP/1.1",
+                "POST /api/v4/groups HTTP/1.1",
+            ]
+        );
+        let team: Value = serde_json::from_str(&requests[2].1).unwrap();
+        assert_eq!((&team["path"], &team["parent_id"]), (&json!("team"), &Value::Null));
+        let sub: Value = serde_json::from_str(&requests[4].1).unwrap();
+        assert_eq!((&sub["path"], &sub["parent_id"]), (&json!("sub"), &json!(1)));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::pool;
+use crate::source::{with_credentials, GitCredential};
+use duct::cmd;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+// Mirrors are bare, so `git lfs fetch` stores objects in `<repo>.git/lfs/`
+// and none of the smudge or clean filters set up by `git lfs install` are
+// needed. Running it would change the global git configuration of whoever
+// runs archivum.
+
+#[derive(Deserialize)]
+struct LsFiles {
+    #[serde(default)]
+    files: Option<Vec<LsFile>>,
+}
+
+#[derive(Deserialize)]
+struct LsFile {
+    oid: String,
+    size: u64,
+}
+
+/// Whether the `git-lfs` extension is installed.
+pub fn available() -> bool {
+    cmd!("git", "lfs", "version")
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Whether a `.gitattributes` file on any branch or tag of the mirror at
+/// `repo_path` tracks files with LFS. Works without git-lfs installed.
+pub fn uses_lfs(repo_path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let refs = cmd!("git", "--git-dir", repo_path, "for-each-ref", "--format=%(objectname)", "refs/heads", "refs/tags").read()?;
+    let tips: BTreeSet<&str> = refs.lines().collect();
+    let tips: Vec<&str> = tips.into_iter().collect();
+
+    // In chunks to stay below the command line length limit
+    for chunk in tips.chunks(100) {
+        let mut args: Vec<OsString> = vec!["--git-dir".into(), repo_path.into()];
+        args.extend(["grep", "-q", "-e", "filter=lfs"].map(OsString::from));
+        args.extend(chunk.iter().map(OsString::from));
+        args.extend(["--", ":(glob)**/.gitattributes"].map(OsString::from));
+        let output = cmd("git", args).unchecked().run()?;
+        match output.status.code() {
+            Some(0) => return Ok(true),
+            Some(1) => continue,
+            _ => return Err(format!("git grep failed in {}", repo_path.display()).into()),
+        }
+    }
+    Ok(false)
+}
+
+/// Fetches every LFS object referenced from any ref of the mirror at
+/// `repo_path`. When the objects not stored yet add up to more than
+/// `max_size` bytes, nothing is fetched.
+pub fn fetch(repo_path: &Path, max_size: Option<u64>, credentials: &[GitCredential]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(max_size) = max_size {
+        let pending = pending_size(repo_path)?;
+        if pending > max_size {
+            return Err(format!(
+                "{} of new LFS objects exceed the limit of {}",
+                pool::format_size(pending),
+                pool::format_size(max_size)
+            )
+            .into());
+        }
+    }
+    with_credentials(cmd!("git", "lfs", "fetch", "--all", "origin").dir(repo_path), credentials).run()?;
+    Ok(())
+}
+
+/// Total size of the LFS objects referenced by the mirror but not stored.
+fn pending_size(repo_path: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+    let output = cmd!("git", "lfs", "ls-files", "--all", "--json").dir(repo_path).read()?;
+    let listing: LsFiles = serde_json::from_str(&output)?;
+    let objects: HashMap<String, u64> = listing
+        .files
+        .unwrap_or_default()
+        .into_iter()
+        .map(|file| (file.oid, file.size))
+        .collect();
+    Ok(objects
+        .iter()
+        .filter(|(oid, _)| oid.len() > 4 && !repo_path.join("lfs/objects").join(&oid[0..2]).join(&oid[2..4]).join(oid).exists())
+        .map(|(_, size)| size)
+        .sum())
+}
+
+/// Whether LFS objects have been fetched into the mirror at `repo_path`.
+pub fn has_objects(repo_path: &Path) -> bool {
+    fs::read_dir(repo_path.join("lfs/objects")).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// Uploads every LFS object of the mirror at `repo_path` to the LFS server
+/// of the remote `url`.
+pub fn push(repo_path: &Path, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    cmd!("git", "lfs", "push", "--all", url).dir(repo_path).run()?;
+    Ok(())
+}
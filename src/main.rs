@@ -1,73 +1,100 @@
 mod actions;
+mod bitbucket;
+mod bundle;
+mod catalog;
 mod commands;
 mod config;
+mod dependency;
+mod destination;
 mod gitea;
+mod git_remote;
 mod github;
-use clap::{Arg, ArgMatches, Command};
+mod gitlab;
+mod lfs;
+mod metadata;
+mod pool;
+mod s3;
+mod secret;
+mod source;
+mod sourcehut;
+mod submodule;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::path::Path;
 
 fn main() {
     let matches = Command::new("archivum")
         .version("0.1.0")
         .author("Your Name <your.email@example.com>")
         .about("Mirrors GitHub repositories for a specified user or organization")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("CONFIG_FILE")
+                .help("Specifies the path to the configuration file")
+                .env("ARCHIVUM_CONFIG")
+                .default_value("config.toml")
+                .global(true),
+        )
+        .arg(
+            Arg::new("output-dir")
+                .short('o')
+                .long("output-dir")
+                .value_name("DIR")
+                .help("Directory repositories are mirrored into")
+                .env("ARCHIVUM_OUTPUT_DIR")
+                .global(true),
+        )
+        .arg(list_arg("user", "ARCHIVUM_USERS", "GitHub user to mirror (repeatable)"))
+        .arg(list_arg("org", "ARCHIVUM_ORGANIZATIONS", "GitHub organization to mirror (repeatable)"))
+        .arg(list_arg("repo", "ARCHIVUM_REPOSITORIES", "Repository to mirror as owner/repo (repeatable)"))
+        .arg(value_arg("github-token", "ARCHIVUM_GITHUB_TOKEN", "GitHub token passed to the GitHub CLI").hide_env_values(true))
+        .arg(value_arg("gitea-url", "ARCHIVUM_GITEA_URL", "Base URL of the Gitea instance"))
+        .arg(value_arg("gitea-token", "ARCHIVUM_GITEA_TOKEN", "Gitea API token").hide_env_values(true))
+        .arg(value_arg("gitea-username", "ARCHIVUM_GITEA_USERNAME", "Gitea username used for pushing"))
+        .arg(value_arg("gitea-password", "ARCHIVUM_GITEA_PASSWORD", "Gitea password used for pushing").hide_env_values(true))
         .subcommand(
             Command::new("mirror")
-                .about("Mirrors repositories based on the configuration file")
-                .arg(
-                    Arg::new("config")
-                        .short('c')
-                        .long("config")
-                        .value_name("CONFIG_FILE")
-                        .help("Specifies the path to the configuration file")
-                        .default_value("config.toml"),
-                ),
+                .about("Mirrors repositories based on the configuration file"),
         )
         .subcommand(
             Command::new("mirror-starred")
-                .about("Mirrors starred repositories based on the configuration file")
-                .arg(
-                    Arg::new("config")
-                        .short('c')
-                        .long("config")
-                        .value_name("CONFIG_FILE")
-                        .help("Specifies the path to the configuration file")
-                        .default_value("config.toml"),
-                ),
+                .about("Mirrors starred repositories based on the configuration file"),
         )
         .subcommand(
             Command::new("download")
-                .about("Downloads repositories based on the configuration file")
-                .arg(
-                    Arg::new("config")
-                        .short('c')
-                        .long("config")
-                        .value_name("CONFIG_FILE")
-                        .help("Specifies the path to the configuration file")
-                        .default_value("config.toml"),
-                ),
+                .about("Downloads repositories based on the configuration file"),
+        )
+        .subcommand(
+            Command::new("download-repo")
+                .about("Downloads a single GitHub repository")
+                .arg(Arg::new("owner").required(true).help("User or organization owning the repository"))
+                .arg(Arg::new("name").required(true).help("Name of the repository")),
         )
         .subcommand(
             Command::new("download-starred")
-                .about("Downloads starred repositories based on the configuration file")
-                .arg(
-                    Arg::new("config")
-                        .short('c')
-                        .long("config")
-                        .value_name("CONFIG_FILE")
-                        .help("Specifies the path to the configuration file")
-                        .default_value("config.toml"),
-                ),
+                .about("Downloads starred repositories based on the configuration file"),
         )
         .subcommand(
             Command::new("upload")
-                .about("Uploads repositories based on the configuration file")
-                .arg(
-                    Arg::new("config")
-                        .short('c')
-                        .long("config")
-                        .value_name("CONFIG_FILE")
-                        .help("Specifies the path to the configuration file")
-                        .default_value("config.toml"),
+                .about("Uploads repositories based on the configuration file"),
+        )
+        .subcommand(
+            Command::new("dedupe")
+                .about("Shares objects between archived repositories with common history"),
+        )
+        .subcommand(
+            Command::new("maintain")
+                .about("Repacks archived repositories and collects garbage in object pools"),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Inspects the configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("check")
+                        .about("Validates the configuration and verifies credentials"),
                 ),
         )
         .get_matches();
@@ -80,23 +107,94 @@ fn main() {
         Some(("download", sub_matches)) => {
             execute_command(sub_matches, commands::download::execute)
         }
+        Some(("download-repo", sub_matches)) => {
+            let owner = sub_matches.get_one::<String>("owner").expect("required");
+            let name = sub_matches.get_one::<String>("name").expect("required");
+            execute_command(sub_matches, |config| commands::download_repo::execute(config, owner, name))
+        }
         Some(("download-starred", sub_matches)) => {
             execute_command(sub_matches, commands::download_starred::execute)
         }
         Some(("upload", sub_matches)) => execute_command(sub_matches, commands::upload::execute),
+        Some(("dedupe", sub_matches)) => execute_command(sub_matches, commands::dedupe::execute),
+        Some(("maintain", sub_matches)) => execute_command(sub_matches, commands::maintain::execute),
+        Some(("config", config_matches)) => {
+            if let Some(("check", sub_matches)) = config_matches.subcommand() {
+                let result = commands::config_check::execute(
+                    config_file(sub_matches),
+                    overrides(sub_matches),
+                );
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         _ => {
-            eprintln!("No valid subcommand was used. Use 'archivum mirror', 'archivum mirror-starred', 'archivum download', 'archivum download-starred', or 'archivum upload' to run the commands.");
+            eprintln!("No valid subcommand was used. Use 'archivum mirror', 'archivum mirror-starred', 'archivum download', 'archivum download-repo', 'archivum download-starred', 'archivum upload', 'archivum dedupe', 'archivum maintain' or 'archivum config check' to run the commands.");
             std::process::exit(1);
         }
     }
 }
 
+fn list_arg(name: &'static str, env: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("NAME")
+        .help(help)
+        .env(env)
+        .value_delimiter(',')
+        .action(ArgAction::Append)
+        .global(true)
+}
+
+fn value_arg(name: &'static str, env: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("VALUE")
+        .help(help)
+        .env(env)
+        .global(true)
+}
+
+fn overrides(matches: &ArgMatches) -> config::Overrides {
+    let string = |name: &str| matches.get_one::<String>(name).cloned();
+    let list = |name: &str| {
+        matches
+            .get_many::<String>(name)
+            .map(|values| values.cloned().collect())
+    };
+    config::Overrides {
+        output_dir: string("output-dir"),
+        users: list("user"),
+        organizations: list("org"),
+        repositories: list("repo"),
+        github_token: string("github-token"),
+        gitea_url: string("gitea-url"),
+        gitea_token: string("gitea-token"),
+        gitea_username: string("gitea-username"),
+        gitea_password: string("gitea-password"),
+    }
+}
+
+/// Returns the configuration file to read. The default config.toml is
+/// optional; an explicitly named file must exist.
+fn config_file(sub_matches: &ArgMatches) -> Option<&Path> {
+    let config_path = sub_matches.get_one::<String>("config").expect("has default");
+    match sub_matches.value_source("config") {
+        Some(ValueSource::DefaultValue) if !Path::new(config_path).exists() => None,
+        _ => Some(Path::new(config_path)),
+    }
+}
+
 fn execute_command<F>(sub_matches: &ArgMatches, command: F)
 where
     F: Fn(&config::Config) -> Result<(), Box<dyn std::error::Error>>,
 {
-    let config_path = sub_matches.get_one::<String>("config").expect("required");
-    match config::Config::from_file(config_path) {
+    let config_path = sub_matches.get_one::<String>("config").expect("has default");
+    let config_file = config_file(sub_matches);
+
+    match config::Config::load(config_file, overrides(sub_matches)) {
         Ok(config) => {
             if let Err(e) = command(&config) {
                 eprintln!("Error executing command: {}", e);
@@ -104,11 +202,13 @@ where
             }
         }
         Err(e) => {
-            eprintln!("Error reading configuration file: {}", e);
-            eprintln!(
-                "Make sure the file '{}' exists and is properly formatted.",
-                config_path
-            );
+            eprintln!("Error reading configuration: {}", e);
+            if config_file.is_some() {
+                eprintln!(
+                    "Make sure the file '{}' exists and is properly formatted.",
+                    config_path
+                );
+            }
             std::process::exit(1);
         }
     }
@@ -0,0 +1,362 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the layout of the files under a `.meta` directory, stored in
+/// each of them so later releases can migrate older archives.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Issues, pull requests, labels, milestones and releases of a repository,
+/// stored next to its mirror as `<repo>.meta/`:
+///
+/// - `state.json`: format version and where the next incremental fetch starts
+/// - `labels.json` and `milestones.json`: lists as returned by the forge
+/// - `issues/<number>.json`: an issue or pull request with its comments
+/// - `releases.json`: every release with the checksums of its assets
+/// - `releases/<tag>/`: the asset files and a `SHA256SUMS` file
+pub struct MetadataArchive {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    pub version: u32,
+    /// `updated_at` of the most recently updated issue archived so far.
+    #[serde(default)]
+    pub issues_since: Option<String>,
+}
+
+/// A release as returned by the forge, with the assets stored for it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ReleaseRecord {
+    pub release: Value,
+    #[serde(default)]
+    pub assets: Vec<AssetRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AssetRecord {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Location of the file relative to the `.meta` directory.
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Releases {
+    version: u32,
+    releases: Vec<ReleaseRecord>,
+}
+
+/// An issue or pull request as returned by the forge, with its discussion.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct IssueRecord {
+    pub version: u32,
+    pub issue: Value,
+    #[serde(default)]
+    pub comments: Vec<Value>,
+    /// Details of a pull request, such as its branches and merge state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_request: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reviews: Vec<Value>,
+    /// Comments on the diff of a pull request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub review_comments: Vec<Value>,
+}
+
+impl IssueRecord {
+    /// The comments, reviews and review comments of the issue as text
+    /// attributed to their original authors, oldest first, for recreating
+    /// the discussion at a destination.
+    pub fn attributed_comments(&self) -> Vec<String> {
+        let mut comments: Vec<(&str, String)> = Vec::new();
+        for comment in &self.comments {
+            let body = attributed("comment", comment, "created_at", comment["body"].as_str().unwrap_or_default());
+            comments.push((comment["created_at"].as_str().unwrap_or_default(), body));
+        }
+        for review in self.reviews.iter().filter(|review| review["body"].as_str().is_some_and(|body| !body.is_empty())) {
+            let body = attributed("review", review, "submitted_at", review["body"].as_str().unwrap_or_default());
+            comments.push((review["submitted_at"].as_str().unwrap_or_default(), body));
+        }
+        for comment in &self.review_comments {
+            let text = format!("`{}`:\n\n{}", comment["path"].as_str().unwrap_or_default(), comment["body"].as_str().unwrap_or_default());
+            comments.push((comment["created_at"].as_str().unwrap_or_default(), attributed("review comment", comment, "created_at", &text)));
+        }
+        comments.sort_by(|a, b| a.0.cmp(b.0));
+        comments.into_iter().map(|(_, body)| body).collect()
+    }
+}
+
+/// Prefixes `text` with a line naming the original author, date and URL of
+/// an archived issue or comment.
+pub fn attributed(kind: &str, item: &Value, date_field: &str, text: &str) -> String {
+    format!(
+        "> Archived {} {} by @{} on {}\n\n{}",
+        kind,
+        item["html_url"].as_str().unwrap_or_default(),
+        item["user"]["login"].as_str().unwrap_or("ghost"),
+        item[date_field].as_str().unwrap_or_default(),
+        text
+    )
+}
+
+/// The original URL in the first line written by `attributed`, which
+/// recognizes items recreated by earlier runs.
+pub fn origin_of(body: &str) -> Option<&str> {
+    let line = body.lines().next()?.strip_prefix("> Archived ")?;
+    line.split(' ').find(|word| word.starts_with("http"))
+}
+
+impl MetadataArchive {
+    /// The archive belonging to the mirror at `repo_path`.
+    pub fn for_mirror(repo_path: &Path) -> Self {
+        MetadataArchive {
+            dir: repo_path.with_extension("meta"),
+        }
+    }
+
+    /// The `.meta` directory itself.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn has_issues(&self) -> bool {
+        self.dir.join("state.json").exists()
+    }
+
+    pub fn has_releases(&self) -> bool {
+        self.dir.join("releases.json").exists()
+    }
+
+    pub fn state(&self) -> Result<State, Box<dyn std::error::Error>> {
+        let state: State = read_json(&self.dir.join("state.json"))?.unwrap_or_default();
+        if state.version > FORMAT_VERSION {
+            return Err(format!("{} was written by a newer version of archivum", self.dir.display()).into());
+        }
+        Ok(state)
+    }
+
+    pub fn save_state(&self, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir.join("state.json"), state)
+    }
+
+    pub fn labels(&self) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        Ok(read_json(&self.dir.join("labels.json"))?.unwrap_or_default())
+    }
+
+    pub fn save_labels(&self, labels: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir.join("labels.json"), &labels)
+    }
+
+    pub fn milestones(&self) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        Ok(read_json(&self.dir.join("milestones.json"))?.unwrap_or_default())
+    }
+
+    pub fn save_milestones(&self, milestones: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir.join("milestones.json"), &milestones)
+    }
+
+    pub fn save_issue(&self, number: u64, record: &IssueRecord) -> Result<(), Box<dyn std::error::Error>> {
+        write_json(&self.dir.join("issues").join(format!("{}.json", number)), record)
+    }
+
+    /// Every archived issue and pull request, ordered by number.
+    pub fn issues(&self) -> Result<Vec<IssueRecord>, Box<dyn std::error::Error>> {
+        let dir = self.dir.join("issues");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut records = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                records.extend(read_json::<IssueRecord>(&path)?);
+            }
+        }
+        records.sort_by_key(|record| record.issue["number"].as_u64());
+        Ok(records)
+    }
+
+    pub fn releases(&self) -> Result<Vec<ReleaseRecord>, Box<dyn std::error::Error>> {
+        let releases: Releases = read_json(&self.dir.join("releases.json"))?.unwrap_or_default();
+        if releases.version > FORMAT_VERSION {
+            return Err(format!("{} was written by a newer version of archivum", self.dir.display()).into());
+        }
+        Ok(releases.releases)
+    }
+
+    /// Saves the list of releases and writes a `SHA256SUMS` file, as read by
+    /// `sha256sum -c`, into the directory of each release with assets.
+    pub fn save_releases(&self, releases: &[ReleaseRecord]) -> Result<(), Box<dyn std::error::Error>> {
+        for record in releases {
+            let Some(first) = record.assets.first() else {
+                continue;
+            };
+            let sums: String = record
+                .assets
+                .iter()
+                .map(|asset| format!("{}  {}\n", asset.sha256, asset.path.rsplit('/').next().unwrap_or_default()))
+                .collect();
+            let dir = self.asset_path(first).with_file_name("");
+            fs::write(dir.join("SHA256SUMS"), sums)?;
+        }
+
+        let releases = Releases {
+            version: FORMAT_VERSION,
+            releases: releases.to_vec(),
+        };
+        write_json(&self.dir.join("releases.json"), &releases)
+    }
+
+    pub fn asset_path(&self, asset: &AssetRecord) -> PathBuf {
+        self.dir.join(&asset.path)
+    }
+
+    /// Stores the asset `name` of the release tagged `tag`. `download` writes
+    /// the file to the path it is given, which is only moved into place once
+    /// complete.
+    pub fn store_asset(
+        &self,
+        tag: &str,
+        name: &str,
+        download: impl FnOnce(&Path) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<AssetRecord, Box<dyn std::error::Error>> {
+        // Tags may contain slashes, which would create nested directories
+        let file_name = name.replace(['/', '\\'], "-");
+        let path = format!("releases/{}/{}", tag.replace(['/', '\\'], "-"), file_name);
+        let target = self.dir.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let partial = target.with_file_name(format!("{}.part", file_name));
+        download(&partial)?;
+        let sha256 = sha256_file(&partial)?;
+        let size = fs::metadata(&partial)?.len();
+        fs::rename(&partial, &target)?;
+
+        Ok(AssetRecord {
+            name: name.to_string(),
+            size,
+            sha256,
+            path,
+        })
+    }
+}
+
+/// Hex-encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Snapshots of a user or organization profile, stored in the owner's
+/// directory as `.profile/`:
+///
+/// - `profile.json`: the latest snapshot
+/// - `history/<time>.json`: every snapshot that differed from the one before
+/// - `avatars/<sha256>`: every avatar image seen, named by its checksum
+pub struct ProfileArchive {
+    dir: PathBuf,
+}
+
+/// The public profile of a user or organization.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub login: String,
+    pub organization: bool,
+    pub name: Option<String>,
+    /// Bio of a user or description of an organization.
+    pub description: Option<String>,
+    pub blog: Option<String>,
+    pub location: Option<String>,
+    pub company: Option<String>,
+    pub email: Option<String>,
+    pub created_at: Option<String>,
+    pub avatar_sha256: Option<String>,
+    /// Repositories pinned to the profile, as `owner/repo`.
+    pub pinned: Vec<String>,
+    /// Public members of an organization.
+    pub members: Vec<String>,
+    /// Repository the profile README is shown from, such as `user/user`.
+    pub readme_repo: Option<String>,
+    pub readme: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProfileSnapshot {
+    version: u32,
+    fetched_at: u64,
+    profile: Profile,
+}
+
+impl ProfileArchive {
+    pub fn new(dir: PathBuf) -> Self {
+        ProfileArchive { dir }
+    }
+
+    /// The latest snapshot, if the profile has been archived.
+    pub fn latest(&self) -> Result<Option<Profile>, Box<dyn std::error::Error>> {
+        let snapshot: Option<ProfileSnapshot> = read_json(&self.dir.join("profile.json"))?;
+        Ok(snapshot.map(|snapshot| snapshot.profile))
+    }
+
+    /// Stores `profile` as the latest snapshot, keeping it in the history when
+    /// it changed since the previous run.
+    pub fn save(&self, profile: Profile) -> Result<(), Box<dyn std::error::Error>> {
+        let changed = self.latest()?.as_ref() != Some(&profile);
+        let snapshot = ProfileSnapshot {
+            version: FORMAT_VERSION,
+            fetched_at: crate::catalog::now(),
+            profile,
+        };
+        if changed {
+            write_json(&self.dir.join("history").join(format!("{}.json", snapshot.fetched_at)), &snapshot)?;
+        }
+        write_json(&self.dir.join("profile.json"), &snapshot)
+    }
+
+    /// Stores an avatar image and returns its checksum.
+    pub fn save_avatar(&self, image: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let sha256 = hex::encode(Sha256::digest(image));
+        let path = self.dir.join("avatars").join(&sha256);
+        if !path.exists() {
+            fs::create_dir_all(self.dir.join("avatars"))?;
+            fs::write(&path, image)?;
+        }
+        Ok(sha256)
+    }
+
+    pub fn avatar_path(&self, profile: &Profile) -> Option<PathBuf> {
+        let path = self.dir.join("avatars").join(profile.avatar_sha256.as_ref()?);
+        path.exists().then_some(path)
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+/// Writes through a temporary file so an interrupted run never leaves a
+/// truncated file behind.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use crate::catalog::Catalog;
+use duct::cmd;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory under `output_dir` holding the object pools.
+const POOLS_DIR: &str = ".pools";
+
+// Repositories sharing history, such as a project and its forks, borrow
+// their objects from a pool repository through `objects/info/alternates`.
+// The pool fetches the refs of every member into `refs/members/<hex key>/`,
+// so every object a member can reach is also reachable in the pool and
+// `git gc` there never drops objects a member still needs.
+
+/// Location of the pool named `id`, the root commit its members share.
+pub fn pool_path(output_dir: &Path, id: &str) -> PathBuf {
+    output_dir.join(POOLS_DIR).join(format!("{}.git", id))
+}
+
+/// The root commits of a repository, sorted. Empty for an empty repository.
+pub fn root_commits(repo_path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = cmd!("git", "--git-dir", repo_path, "rev-list", "--max-parents=0", "--all")
+        .stderr_null()
+        .unchecked()
+        .read()?;
+    let mut roots: Vec<String> = output.lines().map(|line| line.to_string()).collect();
+    roots.sort();
+    Ok(roots)
+}
+
+/// Groups the archived repositories into pools. Forks join the pool of the
+/// top of their fork tree in the catalog; other repositories the pool named
+/// after their first root commit. Returns the members of every pool with more
+/// than one, by catalog key.
+pub fn plan(catalog: &Catalog, output_dir: &Path) -> Result<BTreeMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+    let mut roots = BTreeMap::new();
+    for (key, entry) in &catalog.repositories {
+        let repo_path = output_dir.join(&entry.path);
+        if repo_path.exists() {
+            roots.insert(key.as_str(), root_commits(&repo_path)?.into_iter().next());
+        }
+    }
+
+    let mut pools: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for &key in roots.keys() {
+        let mut top = key;
+        // Bounded in case a broken catalog has a cycle
+        for _ in 0..catalog.repositories.len() {
+            match catalog.repositories[top].parent.as_deref() {
+                Some(parent) if roots.contains_key(parent) => top = parent,
+                _ => break,
+            }
+        }
+        if let Some(Some(root)) = roots.get(top) {
+            pools.entry(root.clone()).or_default().push(key.to_string());
+        }
+    }
+    pools.retain(|_, members| members.len() > 1);
+    Ok(pools)
+}
+
+/// Makes the repository at `repo_path` a member of the pool `id`: copies its
+/// refs into the pool, points its alternates at the pool only and repacks it
+/// without the objects the pool already has.
+pub fn join(output_dir: &Path, id: &str, member: &str, repo_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = pool_path(output_dir, id);
+    if !pool.exists() {
+        cmd!("git", "init", "--bare", "--quiet", &pool).run()?;
+    }
+    // Alternates are resolved relative to the objects directory
+    let pool = fs::canonicalize(&pool)?;
+
+    // The member may still borrow from its parent, so the alternates are only
+    // replaced once the pool has every object the member can reach
+    refresh(&pool, member, repo_path)?;
+    fs::write(
+        repo_path.join("objects/info/alternates"),
+        format!("{}\n", pool.join("objects").display()),
+    )?;
+    cmd!("git", "--git-dir", repo_path, "repack", "-a", "-d", "-l", "-q").run()?;
+    Ok(())
+}
+
+/// Every pool with the catalog key and location of each of its members, as
+/// recorded in the catalog by earlier runs too.
+pub fn members(catalog: &Catalog, output_dir: &Path) -> BTreeMap<String, Vec<(String, PathBuf)>> {
+    let mut pools: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for (key, entry) in &catalog.repositories {
+        if let Some(id) = &entry.pool {
+            pools.entry(id.clone()).or_default().push((key.clone(), output_dir.join(&entry.path)));
+        }
+    }
+    pools
+}
+
+/// Runs `git gc` on the pool `id` after fetching the current refs of all its
+/// `members`, so nothing they reference can be pruned.
+pub fn gc(output_dir: &Path, id: &str, members: &[(String, PathBuf)]) -> Result<(), Box<dyn std::error::Error>> {
+    let pool = pool_path(output_dir, id);
+    for (member, repo_path) in members {
+        refresh(&pool, member, repo_path)?;
+    }
+    cmd!("git", "--git-dir", &pool, "gc", "--quiet").run()?;
+    Ok(())
+}
+
+/// Copies the refs of `member` into its namespace in the pool.
+fn refresh(pool: &Path, member: &str, repo_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    // Catalog keys may contain characters refs cannot, such as `~`
+    let refspec = format!("+refs/*:refs/members/{}/*", hex::encode(member));
+    cmd!("git", "--git-dir", pool, "fetch", "--quiet", "--prune", "--no-tags", repo_path, refspec).run()?;
+    Ok(())
+}
+
+/// The archived repositories other repositories borrow objects from through
+/// their alternates, such as forks cloned against their parent. Pools are
+/// not included.
+pub fn borrowed(catalog: &Catalog, output_dir: &Path) -> HashSet<PathBuf> {
+    let pools = fs::canonicalize(output_dir.join(POOLS_DIR)).ok();
+    catalog
+        .repositories
+        .values()
+        .filter_map(|entry| fs::read_to_string(output_dir.join(&entry.path).join("objects/info/alternates")).ok())
+        .flat_map(|alternates| {
+            alternates
+                .lines()
+                .filter_map(|line| Path::new(line).parent().map(Path::to_path_buf))
+                .collect::<Vec<_>>()
+        })
+        .filter(|repo| pools.as_ref().is_none_or(|pools| !repo.starts_with(pools)))
+        .collect()
+}
+
+/// Formats a number of bytes for people, such as `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+/// Total size in bytes of the files under `path`.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| disk_usage(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::CatalogEntry;
+    use crate::commands::{dedupe, maintain};
+    use crate::config::Config;
+    use duct::Expression;
+
+    fn git(dir: &Path, args: &[&str]) -> Expression {
+        let mut full_args = vec!["-C", dir.to_str().unwrap(), "-c", "user.name=t", "-c", "user.email=t@t"];
+        full_args.extend(args);
+        cmd("git", full_args).stdout_null().stderr_null()
+    }
+
+    fn commit(work: &Path, file: &str) {
+        fs::write(work.join(file), file).unwrap();
+        git(work, &["add", file]).run().unwrap();
+        git(work, &["commit", "-q", "-m", file]).run().unwrap();
+    }
+
+    fn assert_members_intact(output_dir: &Path, members: &[&str]) {
+        for member in members {
+            let repo_path = output_dir.join(format!("{}.git", member));
+            git(&repo_path, &["fsck", "--full", "--strict"])
+                .run()
+                .unwrap_or_else(|e| panic!("{} is broken: {}", member, e));
+        }
+    }
+
+    #[test]
+    fn test_pooled_members_survive_dedupe_and_maintain() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work = tmp.path().join("work");
+        let output_dir = tmp.path().join("out");
+        fs::create_dir_all(&work).unwrap();
+        git(&work, &["init", "-q", "-b", "main"]).run().unwrap();
+        commit(&work, "a");
+
+        let keys = ["owner/project", "fork/project", "other/project"];
+        let mut catalog = Catalog::load(&output_dir).unwrap();
+        for member in keys {
+            commit(&work, member.replace('/', "-").as_str());
+            let repo_path = output_dir.join(format!("{}.git", member));
+            git(tmp.path(), &["clone", "-q", "--mirror", work.to_str().unwrap(), repo_path.to_str().unwrap()])
+                .run()
+                .unwrap();
+            let (owner, name) = member.split_once('/').unwrap();
+            catalog.repositories.insert(member.to_string(), CatalogEntry {
+                path: format!("{}.git", member),
+                owner: owner.to_string(),
+                name: name.to_string(),
+                ..CatalogEntry::default()
+            });
+        }
+        catalog.save().unwrap();
+        let config: Config = toml::from_str(&format!("output_dir = {:?}", output_dir.display().to_string())).unwrap();
+
+        dedupe::execute(&config).unwrap();
+        assert_members_intact(&output_dir, &keys);
+        let catalog = Catalog::load(&output_dir).unwrap();
+        assert_eq!(members(&catalog, &output_dir).values().next().map(Vec::len), Some(3));
+
+        // The history the pool holds is rewritten in one member
+        git(&work, &["reset", "-q", "--hard", "HEAD~2"]).run().unwrap();
+        commit(&work, "rewritten");
+        let fork = output_dir.join("fork/project.git");
+        git(&fork, &["fetch", "-q", "--prune", "--force", work.to_str().unwrap(), "+refs/heads/*:refs/heads/*"])
+            .run()
+            .unwrap();
+
+        dedupe::execute(&config).unwrap();
+        assert_members_intact(&output_dir, &keys);
+
+        maintain::execute(&config).unwrap();
+        assert_members_intact(&output_dir, &keys);
+    }
+}
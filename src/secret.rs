@@ -0,0 +1,97 @@
+use duct::cmd;
+use std::env;
+use std::fmt;
+use std::fs;
+
+/// A credential read from the configuration. The value is only reachable
+/// through [`Secret::expose`] and never shows up in `Debug` output.
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            f.write_str("Secret(<empty>)")
+        } else {
+            f.write_str("Secret(<redacted>)")
+        }
+    }
+}
+
+/// Resolves a secret field that may be given inline as `<name>`, read from a
+/// file with `<name>_file`, or produced by a command with `<name>_command`.
+/// All three forms support `${VAR}` interpolation of environment variables.
+pub fn resolve(
+    name: &str,
+    value: Option<String>,
+    file: Option<String>,
+    command: Option<String>,
+) -> Result<Secret, String> {
+    match (value, file, command) {
+        (None, None, None) => Ok(Secret::default()),
+        (Some(value), None, None) => Ok(Secret(interpolate(&value)?)),
+        (None, Some(file), None) => {
+            let path = interpolate(&file)?;
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}_file '{}': {}", name, path, e))?;
+            Ok(Secret(content.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        (None, None, Some(command)) => {
+            let command = interpolate(&command)?;
+            let output = cmd!("sh", "-c", &command)
+                .stderr_null()
+                .read()
+                .map_err(|e| format!("{}_command failed: {}", name, e))?;
+            Ok(Secret(output.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        _ => Err(format!(
+            "Only one of {0}, {0}_file and {0}_command may be set",
+            name
+        )),
+    }
+}
+
+/// Replaces `${VAR}` with the value of the environment variable `VAR`.
+/// `$$` produces a literal `$`.
+pub fn interpolate(input: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+
+        if let Some(stripped) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix('{') {
+            let end = stripped
+                .find('}')
+                .ok_or_else(|| format!("Unterminated '${{' in '{}'", input))?;
+            let var = &stripped[..end];
+            let value = env::var(var)
+                .map_err(|_| format!("Environment variable '{}' is not set", var))?;
+            result.push_str(&value);
+            rest = &stripped[end + 1..];
+        } else {
+            result.push('$');
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
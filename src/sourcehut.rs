@@ -0,0 +1,123 @@
+use crate::config::SourcehutConfig;
+use crate::source::{host_of, RemoteRepo, Source};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+const LIST_QUERY: &str = "query($username: String!, $cursor: Cursor) {
+  user(username: $username) {
+    repositories(cursor: $cursor) {
+      results { name description visibility }
+      cursor
+    }
+  }
+}";
+
+const REPOSITORY_QUERY: &str = "query($username: String!, $name: String!) {
+  user(username: $username) {
+    repository(name: $name) { name description visibility }
+  }
+}";
+
+/// Runs a query against the GraphQL API of git.sr.ht and returns its `data`.
+pub fn query(config: &SourcehutConfig, query: &str, variables: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    if config.token.is_empty() {
+        return Err(format!("The API of {} requires a token", config.url).into());
+    }
+
+    let client = Client::new();
+    let res = client
+        .post(format!("{}/query", config.url))
+        .bearer_auth(config.token.expose())
+        .json(&json!({ "query": query, "variables": variables }))
+        .send()?;
+
+    if !res.status().is_success() {
+        return Err(format!("{} returned {}", res.url(), res.status()).into());
+    }
+    let mut body: Value = res.json()?;
+    if let Some(message) = body["errors"][0]["message"].as_str() {
+        return Err(format!("{} returned an error: {}", config.url, message).into());
+    }
+    Ok(body["data"].take())
+}
+
+pub struct SourcehutSource {
+    forge: String,
+    config: SourcehutConfig,
+}
+
+impl SourcehutSource {
+    pub fn new(forge: &str, config: SourcehutConfig) -> Self {
+        SourcehutSource {
+            forge: forge.to_string(),
+            config,
+        }
+    }
+
+    fn parse_repo(&self, owner: &str, repo: &Value) -> Option<RemoteRepo> {
+        let name = repo["name"].as_str()?;
+        Some(RemoteRepo {
+            description: repo["description"].as_str().map(|s| s.to_string()),
+            private: repo["visibility"].as_str() == Some("PRIVATE"),
+            ..self.repository(owner, name)
+        })
+    }
+}
+
+/// SourceHut owners are `~user`, while the API takes the bare user name.
+fn with_tilde(owner: &str) -> String {
+    format!("~{}", owner.trim_start_matches('~'))
+}
+
+impl Source for SourcehutSource {
+    fn forge(&self) -> &str {
+        &self.forge
+    }
+
+    fn list_repositories(&self, owner: &str) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        let owner = with_tilde(owner);
+        let mut repos = Vec::new();
+        let mut cursor = Value::Null;
+
+        loop {
+            let data = query(
+                &self.config,
+                LIST_QUERY,
+                json!({ "username": &owner[1..], "cursor": cursor }),
+            )?;
+            let page = &data["user"]["repositories"];
+            match &page["results"] {
+                Value::Array(results) => repos.extend(results.iter().filter_map(|repo| self.parse_repo(&owner, repo))),
+                _ => return Err(format!("No SourceHut user named '{}' on {}", owner, self.config.url).into()),
+            }
+
+            match &page["cursor"] {
+                Value::String(next) => cursor = Value::String(next.clone()),
+                _ => return Ok(repos),
+            }
+        }
+    }
+
+    fn list_starred(&self) -> Result<Vec<RemoteRepo>, Box<dyn std::error::Error>> {
+        Err(format!("{} does not support starring repositories", self.config.url).into())
+    }
+
+    fn clone_url(&self, owner: &str, repo: &str) -> String {
+        format!("{}/{}/{}", self.config.url, with_tilde(owner), repo)
+    }
+
+    fn metadata(&self, owner: &str, repo: &str) -> Result<RemoteRepo, Box<dyn std::error::Error>> {
+        let owner = with_tilde(owner);
+        let data = query(
+            &self.config,
+            REPOSITORY_QUERY,
+            json!({ "username": &owner[1..], "name": repo }),
+        )?;
+        self.parse_repo(&owner, &data["user"]["repository"])
+            .ok_or_else(|| format!("No repository {}/{} on {}", owner, repo, self.config.url).into())
+    }
+
+    fn local_path(&self, owner: &str, repo: &str) -> String {
+        format!("{}/{}/{}", host_of(&self.config.url), with_tilde(owner), repo)
+    }
+}
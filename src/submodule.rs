@@ -0,0 +1,95 @@
+use duct::cmd;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// The URLs of every submodule in the `.gitmodules` file of any branch or
+/// tag of the mirror at `repo_path`, as written there.
+pub fn urls(repo_path: &Path) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let refs = cmd!("git", "--git-dir", repo_path, "for-each-ref", "--format=%(objectname)", "refs/heads", "refs/tags").read()?;
+    let tips: BTreeSet<&str> = refs.lines().collect();
+    if tips.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+
+    // Most refs share the same few versions of `.gitmodules`, so each blob
+    // is only parsed once
+    let input: String = tips.iter().map(|tip| format!("{}:.gitmodules\n", tip)).collect();
+    let output = cmd!("git", "--git-dir", repo_path, "cat-file", "--batch-check=%(objectname) %(objecttype)")
+        .stdin_bytes(input)
+        .read()?;
+    let blobs: BTreeSet<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_suffix(" blob"))
+        .collect();
+
+    let mut urls = BTreeSet::new();
+    for blob in blobs {
+        // Exits with 1 when there is no match
+        let entries = cmd!("git", "--git-dir", repo_path, "config", "--blob", blob, "--get-regexp", r"^submodule\..*\.url$")
+            .unchecked()
+            .read()?;
+        urls.extend(entries.lines().filter_map(|line| Some(line.split_once(' ')?.1.trim().to_string())));
+    }
+    Ok(urls)
+}
+
+/// Resolves a submodule URL starting with `./` or `../` against `base`, the
+/// URL of the superproject, the way `git submodule` does. Other URLs are
+/// returned unchanged.
+pub fn resolve_url(base: &str, url: &str) -> Result<String, String> {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return Ok(url.to_string());
+    }
+
+    // Components are never removed from the scheme and host of a URL, and
+    // `host:path` URLs also separate components with `:`
+    let start = base.find("://").map_or(0, |i| i + 3);
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut separator = '/';
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            rest = stripped;
+            let end = base[start..]
+                .rfind(|c| c == '/' || (start == 0 && c == ':'))
+                .ok_or_else(|| format!("'{}' goes above the root of '{}'", url, base))?;
+            separator = base[start + end..].chars().next().unwrap_or('/');
+            base.truncate(start + end);
+        } else {
+            break;
+        }
+    }
+    Ok(format!("{}{}{}", base, separator, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_url_https() {
+        let base = "https://github.com/octocat/app.git";
+        assert_eq!(resolve_url(base, "../lib.git"), Ok("https://github.com/octocat/lib.git".to_string()));
+        assert_eq!(resolve_url(base, "../../other/lib.git"), Ok("https://github.com/other/lib.git".to_string()));
+        assert_eq!(resolve_url(base, "./sub.git"), Ok("https://github.com/octocat/app.git/sub.git".to_string()));
+        assert_eq!(
+            resolve_url("https://github.com/octocat/app/", "./.././lib"),
+            Ok("https://github.com/octocat/lib".to_string())
+        );
+        assert!(resolve_url(base, "../../../lib.git").is_err());
+        assert_eq!(
+            resolve_url(base, "git@gitlab.com:group/lib.git"),
+            Ok("git@gitlab.com:group/lib.git".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_url_scp_like() {
+        let base = "git@github.com:octocat/app.git";
+        assert_eq!(resolve_url(base, "../lib.git"), Ok("git@github.com:octocat/lib.git".to_string()));
+        assert_eq!(resolve_url(base, "../../lib.git"), Ok("git@github.com:lib.git".to_string()));
+        assert!(resolve_url(base, "../../../lib.git").is_err());
+    }
+}